@@ -0,0 +1,275 @@
+use std::ops::Range;
+
+use aoc_utils::{grid::Grid, point::Point};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Neighbourhood {
+    /// cells within this Chebyshev distance
+    Moore(i64),
+    /// cells within this Manhattan distance
+    VonNeumann(i64),
+}
+
+impl Neighbourhood {
+    pub fn offsets(self) -> Vec<Point> {
+        let (Neighbourhood::Moore(r) | Neighbourhood::VonNeumann(r)) = self;
+        let mut res = Vec::new();
+        for y in -r..=r {
+            for x in -r..=r {
+                let inside = match self {
+                    Neighbourhood::Moore(_) => true,
+                    Neighbourhood::VonNeumann(_) => x.abs() + y.abs() <= r,
+                };
+                if inside && (x, y) != (0, 0) {
+                    res.push(Point::new(x, y));
+                }
+            }
+        }
+        res
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Update {
+    /// every cell looks at the grid from before the step
+    Synchronous,
+    /// cells are updated in place in reading order, seeing the changes made before them
+    Asynchronous,
+}
+
+/// A dead cell with a number of live neighbours in `birth` comes alive, and a live cell
+/// stays alive with a number in `survival`. Any byte other than `alive` is a dead cell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub neighbourhood: Neighbourhood,
+    pub birth: Range<usize>,
+    pub survival: Range<usize>,
+    pub update: Update,
+    pub alive: u8,
+    /// written over the cells that die
+    pub dead: u8,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub births: usize,
+    pub deaths: usize,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.births == 0 && self.deaths == 0
+    }
+
+    fn add(&mut self, other: Changes) {
+        self.births += other.births;
+        self.deaths += other.deaths;
+    }
+}
+
+pub struct Automaton {
+    rule: Rule,
+    offsets: Vec<Point>,
+}
+
+impl Automaton {
+    pub fn new(rule: Rule) -> Self {
+        let offsets = rule.neighbourhood.offsets();
+        Automaton { rule, offsets }
+    }
+
+    pub fn live_neighbours(&self, grid: &Grid<u8>, p: Point) -> usize {
+        self.offsets
+            .iter()
+            .map(|&d| p + d)
+            .filter(|&p2| grid.contains(p2) && grid[p2] == self.rule.alive)
+            .count()
+    }
+
+    // the new byte of a cell, if it changes
+    fn next(&self, grid: &Grid<u8>, p: Point) -> Option<u8> {
+        let n = self.live_neighbours(grid, p);
+        if grid[p] == self.rule.alive {
+            (!self.rule.survival.contains(&n)).then_some(self.rule.dead)
+        } else {
+            self.rule.birth.contains(&n).then_some(self.rule.alive)
+        }
+    }
+
+    /// What a synchronous step would change, leaving the grid as it is.
+    pub fn changes(&self, grid: &Grid<u8>) -> Changes {
+        let mut changes = Changes::default();
+        for byte in grid.iter().filter_map(|p| self.next(grid, p)) {
+            if byte == self.rule.alive {
+                changes.births += 1;
+            } else {
+                changes.deaths += 1;
+            }
+        }
+        changes
+    }
+
+    pub fn step(&self, grid: &mut Grid<u8>) -> Changes {
+        let mut changes = Changes::default();
+        let mut count = |byte| {
+            if byte == self.rule.alive {
+                changes.births += 1;
+            } else {
+                changes.deaths += 1;
+            }
+        };
+        match self.rule.update {
+            Update::Synchronous => {
+                let updates: Vec<(Point, u8)> = grid
+                    .iter()
+                    .filter_map(|p| Some((p, self.next(grid, p)?)))
+                    .collect();
+                for (p, byte) in updates {
+                    grid[p] = byte;
+                    count(byte);
+                }
+            }
+            Update::Asynchronous => {
+                for y in 0..grid.height as i64 {
+                    for x in 0..grid.width as i64 {
+                        let p = Point::new(x, y);
+                        if let Some(byte) = self.next(grid, p) {
+                            grid[p] = byte;
+                            count(byte);
+                        }
+                    }
+                }
+            }
+        }
+        changes
+    }
+
+    pub fn run(&self, grid: &mut Grid<u8>, steps: usize) -> Changes {
+        let mut total = Changes::default();
+        for _ in 0..steps {
+            total.add(self.step(grid));
+        }
+        total
+    }
+
+    /// Steps until nothing changes, returning the number of steps that changed something.
+    /// Never returns for rules that keep changing, like most of the Game of Life.
+    pub fn run_to_fixpoint(&self, grid: &mut Grid<u8>) -> (usize, Changes) {
+        let mut total = Changes::default();
+        let mut steps = 0;
+        loop {
+            let changes = self.step(grid);
+            if changes.is_empty() {
+                return (steps, total);
+            }
+            total.add(changes);
+            steps += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn life(update: Update) -> Automaton {
+        Automaton::new(Rule {
+            neighbourhood: Neighbourhood::Moore(1),
+            birth: 3..4,
+            survival: 2..4,
+            update,
+            alive: b'#',
+            dead: b'.',
+        })
+    }
+
+    fn lines(grid: &Grid<u8>) -> Vec<String> {
+        grid.bytes
+            .chunks(grid.width)
+            .map(|row| String::from_utf8(row.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn neighbourhoods() {
+        assert_eq!(Neighbourhood::Moore(1).offsets().len(), 8);
+        assert_eq!(Neighbourhood::Moore(2).offsets().len(), 24);
+        assert_eq!(Neighbourhood::VonNeumann(1).offsets().len(), 4);
+        assert_eq!(Neighbourhood::VonNeumann(2).offsets().len(), 12);
+        assert_eq!(Neighbourhood::Moore(0).offsets().len(), 0);
+    }
+
+    #[test]
+    fn game_of_life() {
+        let life = life(Update::Synchronous);
+        let mut grid = Grid::parse(".....\n..#..\n..#..\n..#..\n.....");
+        let blinker = lines(&grid);
+        let preview = life.changes(&grid);
+        assert_eq!(lines(&grid), blinker);
+        let changes = life.step(&mut grid);
+        assert_eq!(changes, preview);
+        assert_eq!(
+            changes,
+            Changes {
+                births: 2,
+                deaths: 2
+            }
+        );
+        assert_eq!(lines(&grid)[2], ".###.");
+        life.step(&mut grid);
+        assert_eq!(lines(&grid), blinker);
+
+        // a glider moves by one cell diagonally every 4 steps
+        let mut grid = Grid::parse(".#....\n..#...\n###...\n......\n......\n......");
+        life.run(&mut grid, 4);
+        assert_eq!(
+            lines(&grid),
+            ["......", "..#...", "...#..", ".###..", "......", "......"]
+        );
+
+        // a block is a still life
+        let mut grid = Grid::parse("....\n.##.\n.##.\n....");
+        assert_eq!(life.run_to_fixpoint(&mut grid), (0, Changes::default()));
+    }
+
+    #[test]
+    fn asynchronous_updates() {
+        let rule = Rule {
+            neighbourhood: Neighbourhood::VonNeumann(1),
+            birth: 0..0,
+            survival: 2..5,
+            update: Update::Synchronous,
+            alive: b'@',
+            dead: b'.',
+        };
+        let sync = Automaton::new(rule.clone());
+        let mut grid = Grid::parse("@@@");
+        assert_eq!(
+            sync.step(&mut grid),
+            Changes {
+                births: 0,
+                deaths: 2
+            }
+        );
+        assert_eq!(lines(&grid), [".@."]);
+        let mut grid = Grid::parse("@@@");
+        assert_eq!(sync.run_to_fixpoint(&mut grid).0, 2);
+
+        // each cell dies before its right neighbour is looked at
+        let asynchronous = Automaton::new(Rule {
+            update: Update::Asynchronous,
+            ..rule
+        });
+        let mut grid = Grid::parse("@@@");
+        assert_eq!(
+            asynchronous.step(&mut grid),
+            Changes {
+                births: 0,
+                deaths: 3
+            }
+        );
+        assert_eq!(lines(&grid), ["..."]);
+        let mut grid = Grid::parse("@@@");
+        assert_eq!(asynchronous.run_to_fixpoint(&mut grid).0, 1);
+    }
+}
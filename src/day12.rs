@@ -1,3 +1,8 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
 use aoc_runner_derive::{aoc, aoc_generator};
 use aoc_utils::{grid::Grid, point::Point};
 
@@ -28,6 +33,52 @@ impl Shape {
         }
         rotations
     }
+
+    /// Translates the shape so that its bounding box starts at (0, 0),
+    /// with the points sorted in reading order.
+    pub fn normalized(&self) -> Shape {
+        let min_x = self.points.iter().map(|p| p.x).min().unwrap_or(0);
+        let min_y = self.points.iter().map(|p| p.y).min().unwrap_or(0);
+        let mut points: Vec<Point> = self
+            .points
+            .iter()
+            .map(|p| Point::new(p.x - min_x, p.y - min_y))
+            .collect();
+        points.sort_unstable_by_key(|p| (p.y, p.x));
+        Shape { points }
+    }
+
+    /// All distinct rotations and reflections of the shape, normalized.
+    ///
+    /// Unlike `_all_rotations`, this works for shapes of any size.
+    pub fn orientations(&self) -> Vec<Shape> {
+        let mut res: Vec<Shape> = Vec::new();
+        let mut current = self.normalized();
+        for _ in 0..2 {
+            for _ in 0..4 {
+                if !res.contains(&current) {
+                    res.push(current.clone());
+                }
+                let rotated = Shape {
+                    points: current
+                        .points
+                        .iter()
+                        .map(|p| Point::new(-p.y, p.x))
+                        .collect(),
+                };
+                current = rotated.normalized();
+            }
+            let mirrored = Shape {
+                points: current
+                    .points
+                    .iter()
+                    .map(|p| Point::new(-p.x, p.y))
+                    .collect(),
+            };
+            current = mirrored.normalized();
+        }
+        res
+    }
 }
 
 #[aoc_generator(day12)]
@@ -133,47 +184,458 @@ fn _solve(
     false
 }
 
-#[aoc(day12, part1)]
-pub fn part1(input: &Input) -> usize {
-    // nice troll by the creator for the last day:
-    // after writing a very wonky super slow backtracking recursive solver, it returned the correct answer instantly for the real input
-    // that's because on the real input, each time an input is invalid, it will ask for more cells
-    // than available, so the early termination kicks in and it returns false instantly
-    //
-    // I'll leave the backtracking solver code for posterity (very inefficient one)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+    Continue,
+    Stop,
+    OutOfBudget,
+}
 
-    let (shapes, regions) = input;
-    let mut res = 0;
-    for region in regions {
-        let (rx, ry, n_shapes) = region;
+/// Limits for a single region search, unlimited by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    pub max_nodes: Option<u64>,
+    pub max_time: Option<Duration>,
+}
+
+/// Outcome of a region search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    Fits,
+    DoesNotFit,
+    /// The budget ran out before the search could decide.
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackError {
+    /// A region asks for copies of a shape that isn't defined.
+    UnknownShape { shape: usize, shapes: usize },
+}
 
-        let num_cells = rx * ry;
-        let mut req_cells = 0;
-        for (i, &n) in n_shapes.iter().enumerate() {
-            req_cells += n * shapes[i].points.len();
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackError::UnknownShape { shape, shapes } => {
+                write!(f, "shape {shape} requested but only {shapes} defined")
+            }
         }
-        if num_cells >= req_cells {
-            res += 1;
+    }
+}
+
+impl std::error::Error for PackError {}
+
+/// Exact cover solver for packing shapes into a rectangular region.
+///
+/// Cells are secondary columns (they can be left empty as long as there is slack),
+/// and each shape is a primary column that must be used exactly `n` times.
+/// Identical pieces are never told apart, so each packing is found exactly once.
+pub struct Packer {
+    width: usize,
+    height: usize,
+    // for each shape, every orientation as offsets relative to its first cell in reading order
+    orientations: Vec<Vec<Vec<(i64, i64)>>>,
+    filled: Vec<bool>,
+    remaining: Vec<usize>,
+    pieces_left: usize,
+    slack: usize,
+    // (shape, covered cells) for every piece currently placed
+    placed: Vec<(usize, Vec<usize>)>,
+    nodes: u64,
+    max_nodes: u64,
+    deadline: Option<Instant>,
+}
+
+impl Packer {
+    /// Returns `None` when the pieces need more cells than the region has.
+    pub fn new(
+        shapes: &[Shape],
+        width: usize,
+        height: usize,
+        n_shapes: &[usize],
+    ) -> Result<Option<Self>, PackError> {
+        if let Some(shape) = (shapes.len()..n_shapes.len()).find(|&i| n_shapes[i] > 0) {
+            return Err(PackError::UnknownShape {
+                shape,
+                shapes: shapes.len(),
+            });
         }
+        let n_shapes = &n_shapes[..n_shapes.len().min(shapes.len())];
+        let req_cells: usize = n_shapes
+            .iter()
+            .zip(shapes)
+            .map(|(&n, shape)| n * shape.points.len())
+            .sum();
+        let Some(slack) = (width * height).checked_sub(req_cells) else {
+            return Ok(None);
+        };
+        // pieces are free to rotate and flip, so the region can be transposed at will:
+        // filling along the short side finds dead ends much sooner
+        let (width, height) = (width.min(height), width.max(height));
 
-        /* let all_rotations: Vec<Vec<Shape>> = shapes.iter().map(|s| s._all_rotations()).collect();
-        let mut expanded: Vec<(Vec<Shape>, usize)> = Vec::new();
-        for (i, n) in n_shapes.iter().enumerate() {
-            for _ in 0..*n {
-                expanded.push((all_rotations[i].clone(), i));
+        let mut orientations = Vec::with_capacity(n_shapes.len());
+        for shape in shapes.iter().take(n_shapes.len()) {
+            let mut offsets = Vec::new();
+            for o in shape.orientations() {
+                let first = o.points[0];
+                offsets.push(
+                    o.points
+                        .iter()
+                        .map(|p| (p.x - first.x, p.y - first.y))
+                        .collect(),
+                );
             }
+            orientations.push(offsets);
+        }
+
+        Ok(Some(Packer {
+            width,
+            height,
+            orientations,
+            filled: vec![false; width * height],
+            remaining: n_shapes.to_vec(),
+            pieces_left: n_shapes.iter().sum(),
+            slack,
+            placed: Vec::new(),
+            nodes: 0,
+            max_nodes: u64::MAX,
+            deadline: None,
+        }))
+    }
+
+    fn out_of_budget(&mut self) -> bool {
+        self.nodes += 1;
+        if self.nodes > self.max_nodes {
+            return true;
         }
-        let mut grid = Grid {
-            width: *rx,
-            height: *ry,
-            bytes: vec![b'.'; rx * ry],
+        // checking the clock on every node is too costly
+        match self.deadline {
+            Some(deadline) if self.nodes.is_multiple_of(1024) => Instant::now() >= deadline,
+            _ => false,
+        }
+    }
+
+    fn cells_for(&self, anchor: usize, offsets: &[(i64, i64)]) -> Option<Vec<usize>> {
+        let ax = (anchor % self.width) as i64;
+        let ay = (anchor / self.width) as i64;
+        let mut cells = Vec::with_capacity(offsets.len());
+        for &(dx, dy) in offsets {
+            let (x, y) = (ax + dx, ay + dy);
+            if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+                return None;
+            }
+            let idx = y as usize * self.width + x as usize;
+            if self.filled[idx] {
+                return None;
+            }
+            cells.push(idx);
+        }
+        Some(cells)
+    }
+
+    // every cell before `cursor` is already decided (covered or left empty)
+    fn search(&mut self, cursor: usize, on_solution: &mut impl FnMut(&Packer) -> Flow) -> Flow {
+        if self.pieces_left == 0 {
+            return on_solution(self);
+        }
+        if self.out_of_budget() {
+            return Flow::OutOfBudget;
+        }
+        let Some(anchor) = (cursor..self.filled.len()).find(|&i| !self.filled[i]) else {
+            return Flow::Continue;
         };
-        let remaining_cells = rx * ry;
-        if _solve(&mut grid, &expanded, 0, remaining_cells) {
-            res += 1;
-        } */
+
+        // the first undecided cell is either covered by a piece whose first cell lands on it...
+        for shape in 0..self.remaining.len() {
+            if self.remaining[shape] == 0 {
+                continue;
+            }
+            for o in 0..self.orientations[shape].len() {
+                let Some(cells) = self.cells_for(anchor, &self.orientations[shape][o]) else {
+                    continue;
+                };
+                for &c in &cells {
+                    self.filled[c] = true;
+                }
+                self.remaining[shape] -= 1;
+                self.pieces_left -= 1;
+                self.placed.push((shape, cells));
+
+                let flow = self.search(anchor + 1, on_solution);
+
+                let (_, cells) = self.placed.pop().unwrap();
+                self.pieces_left += 1;
+                self.remaining[shape] += 1;
+                for c in cells {
+                    self.filled[c] = false;
+                }
+                if flow != Flow::Continue {
+                    return flow;
+                }
+            }
+        }
+
+        // ...or left empty
+        if self.slack > 0 {
+            self.slack -= 1;
+            self.filled[anchor] = true;
+            let flow = self.search(anchor + 1, on_solution);
+            self.filled[anchor] = false;
+            self.slack += 1;
+            if flow != Flow::Continue {
+                return flow;
+            }
+        }
+        Flow::Continue
+    }
+
+    // cell mappings for every symmetry of the region, identity excluded
+    fn region_symmetries(&self) -> Vec<Vec<usize>> {
+        let (w, h) = (self.width, self.height);
+        // only squares can be rotated by a quarter turn or transposed
+        let n_symmetries = if w == h { 8 } else { 4 };
+        (1..n_symmetries)
+            .map(|t| {
+                (0..w * h)
+                    .map(|i| {
+                        let (x, y) = (i % w, i / w);
+                        let (x, y) = match t {
+                            1 => (w - 1 - x, y),
+                            2 => (x, h - 1 - y),
+                            3 => (w - 1 - x, h - 1 - y),
+                            4 => (y, x),
+                            5 => (w - 1 - y, x),
+                            6 => (y, h - 1 - x),
+                            _ => (w - 1 - y, h - 1 - x),
+                        };
+                        y * w + x
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    // true if every piece gets its own bounding box, laid out on a simple grid
+    fn fits_trivially(&self) -> bool {
+        let (mut bw, mut bh) = (0, 0);
+        for (shape, orientations) in self.orientations.iter().enumerate() {
+            if self.remaining[shape] == 0 {
+                continue;
+            }
+            let offsets = &orientations[0];
+            let min_dx = offsets.iter().map(|o| o.0).min().unwrap();
+            let max_dx = offsets.iter().map(|o| o.0).max().unwrap();
+            let max_dy = offsets.iter().map(|o| o.1).max().unwrap();
+            bw = bw.max((max_dx - min_dx + 1) as usize);
+            bh = bh.max((max_dy + 1) as usize);
+        }
+        let boxes = |bw: usize, bh: usize| (self.width / bw.max(1)) * (self.height / bh.max(1));
+        boxes(bw, bh).max(boxes(bh, bw)) >= self.pieces_left
+    }
+
+    /// Looks for a single packing, giving up with [`Fit::Unknown`] once the budget is spent.
+    pub fn solve(&mut self, budget: Budget) -> Fit {
+        if self.fits_trivially() {
+            return Fit::Fits;
+        }
+        self.nodes = 0;
+        self.max_nodes = budget.max_nodes.unwrap_or(u64::MAX);
+        self.deadline = budget.max_time.map(|t| Instant::now() + t);
+        match self.search(0, &mut |_| Flow::Stop) {
+            Flow::Stop => Fit::Fits,
+            Flow::Continue => Fit::DoesNotFit,
+            Flow::OutOfBudget => Fit::Unknown,
+        }
+    }
+
+    /// Counts the distinct packings of the region, see [`CountOptions`].
+    pub fn count(&mut self, opts: CountOptions) -> PackingCount {
+        let symmetries = if opts.up_to_symmetry {
+            self.region_symmetries()
+        } else {
+            Vec::new()
+        };
+        let cap = opts.cap.unwrap_or(usize::MAX);
+        self.max_nodes = u64::MAX;
+        self.deadline = None;
+        let mut count = 0;
+        let mut capped = false;
+
+        let mut on_solution = |packer: &Packer| {
+            if !symmetries.is_empty() && !is_canonical(&packer.placed, &symmetries) {
+                return Flow::Continue;
+            }
+            // one packing past the cap tells a truncated count from an exact one
+            if count == cap {
+                capped = true;
+                return Flow::Stop;
+            }
+            count += 1;
+            Flow::Continue
+        };
+        self.search(0, &mut on_solution);
+        PackingCount { count, capped }
+    }
+}
+
+// a packing is canonical if no symmetry of the region maps it to a smaller one,
+// so exactly one packing per orbit is canonical
+fn is_canonical(placed: &[(usize, Vec<usize>)], symmetries: &[Vec<usize>]) -> bool {
+    let mut packing = placed.to_vec();
+    packing.sort_unstable();
+    for map in symmetries {
+        let mut image: Vec<(usize, Vec<usize>)> = packing
+            .iter()
+            .map(|(shape, cells)| {
+                let mut cells: Vec<usize> = cells.iter().map(|&c| map[c]).collect();
+                cells.sort_unstable();
+                (*shape, cells)
+            })
+            .collect();
+        image.sort_unstable();
+        if image < packing {
+            return false;
+        }
+    }
+    true
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CountOptions {
+    /// Stop once this many packings have been counted.
+    pub cap: Option<usize>,
+    /// Count packings that only differ by a rotation/reflection of the region once.
+    pub up_to_symmetry: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackingCount {
+    pub count: usize,
+    /// True if there are more packings than the cap, `count` being the cap then.
+    pub capped: bool,
+}
+
+/// Counts the distinct ways to pack `n_shapes[i]` copies of each `shapes[i]` into a
+/// `width`x`height` region, pieces being free to rotate and flip.
+pub fn count_packings(
+    shapes: &[Shape],
+    width: usize,
+    height: usize,
+    n_shapes: &[usize],
+    opts: CountOptions,
+) -> Result<PackingCount, PackError> {
+    Ok(match Packer::new(shapes, width, height, n_shapes)? {
+        Some(mut packer) => packer.count(opts),
+        None => PackingCount {
+            count: 0,
+            capped: false,
+        },
+    })
+}
+
+/// Decides whether the pieces of a region can all be packed into it.
+pub fn solve_region(
+    shapes: &[Shape],
+    region: &(usize, usize, Vec<usize>),
+    budget: Budget,
+) -> Result<Fit, PackError> {
+    let (width, height, n_shapes) = region;
+    Ok(match Packer::new(shapes, *width, *height, n_shapes)? {
+        Some(mut packer) => packer.solve(budget),
+        None => Fit::DoesNotFit,
+    })
+}
+
+/// Solves every region on `threads` worker threads, each region with its own budget.
+pub fn solve_regions(
+    shapes: &[Shape],
+    regions: &[(usize, usize, Vec<usize>)],
+    budget: Budget,
+    threads: usize,
+) -> Result<Vec<Fit>, PackError> {
+    let next = AtomicUsize::new(0);
+    let mut res = vec![Ok(Fit::Unknown); regions.len()];
+    thread::scope(|s| {
+        let workers: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut solved = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= regions.len() {
+                            break;
+                        }
+                        solved.push((i, solve_region(shapes, &regions[i], budget)));
+                    }
+                    solved
+                })
+            })
+            .collect();
+        for worker in workers {
+            for (i, fit) in worker.join().unwrap() {
+                res[i] = fit;
+            }
+        }
+    });
+    res.into_iter().collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionReport {
+    pub fits: usize,
+    pub decided: usize,
+    pub timed_out: usize,
+}
+
+impl RegionReport {
+    pub fn new(fits: &[Fit]) -> Self {
+        let timed_out = fits.iter().filter(|&&f| f == Fit::Unknown).count();
+        RegionReport {
+            fits: fits.iter().filter(|&&f| f == Fit::Fits).count(),
+            decided: fits.len() - timed_out,
+            timed_out,
+        }
+    }
+}
+
+impl fmt::Display for RegionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({} decided, {} timed out)",
+            self.fits, self.decided, self.timed_out
+        )
     }
-    res
+}
+
+// a node limit rather than a time limit, so the answer doesn't depend on the machine.
+// the example regions are decided in less than 100k nodes
+const PART1_BUDGET: Budget = Budget {
+    max_nodes: Some(1_000_000),
+    max_time: None,
+};
+
+#[aoc(day12, part1)]
+pub fn part1(input: &Input) -> Result<RegionReport, PackError> {
+    // nice troll by the creator for the last day:
+    // after writing a very wonky super slow backtracking recursive solver, it returned the correct answer instantly for the real input
+    // that's because on the real input, each time an input is invalid, it will ask for more cells
+    // than available, so the early termination kicks in and it returns false instantly
+    //
+    // I'll leave the backtracking solver code for posterity (very inefficient one)
+    //
+    // the packer does the same area check up front, and every valid region of the real input
+    // has enough room to give each piece its own 3x3 box, so it never needs to search.
+    // the budget only matters for hard regions like the example ones.
+
+    let (shapes, regions) = input;
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    Ok(RegionReport::new(&solve_regions(
+        shapes,
+        regions,
+        PART1_BUDGET,
+        threads,
+    )?))
 }
 
 #[aoc(day12, part2)]
@@ -221,13 +683,188 @@ mod test {
 
     #[test]
     fn part1_example() {
-        // the area solution doesn't work for test input
-        // it should be 2 but the area check returns 3
-        assert_eq!(part1(&parse(EXAMPLE)), 3);
+        // the area check alone would say 3 here
+        let report = part1(&parse(EXAMPLE)).unwrap();
+        assert_eq!(
+            report,
+            RegionReport {
+                fits: 2,
+                decided: 3,
+                timed_out: 0
+            }
+        );
+        assert_eq!(report.to_string(), "2 (3 decided, 0 timed out)");
     }
 
     #[test]
     fn part2_example() {
         assert_eq!(part2(&parse(EXAMPLE)), 0);
     }
+
+    fn shape(rows: &str) -> Shape {
+        let mut points = Vec::new();
+        for (y, l) in rows.split('/').enumerate() {
+            for (x, c) in l.chars().enumerate() {
+                if c == '#' {
+                    points.push(Point::new(x as i64, y as i64));
+                }
+            }
+        }
+        Shape { points }
+    }
+
+    fn pentominoes() -> Vec<Shape> {
+        [
+            ".##/##./.#.",
+            "#####",
+            "####/#...",
+            "###./..##",
+            "###/##.",
+            "###/.#./.#.",
+            "#.#/###",
+            "#../#../###",
+            "#../##./.##",
+            ".#./###/.#.",
+            "####/.#..",
+            "##./.#./.##",
+        ]
+        .iter()
+        .map(|rows| shape(rows))
+        .collect()
+    }
+
+    #[test]
+    fn orientations_count() {
+        let counts: Vec<usize> = pentominoes()
+            .iter()
+            .map(|s| s.orientations().len())
+            .collect();
+        assert_eq!(counts, vec![8, 2, 8, 8, 8, 4, 4, 4, 4, 1, 8, 4]);
+    }
+
+    #[test]
+    fn count_domino_tilings() {
+        let domino = [shape("##")];
+        let all = count_packings(&domino, 4, 2, &[4], CountOptions::default()).unwrap();
+        assert_eq!(
+            all,
+            PackingCount {
+                count: 5,
+                capped: false
+            }
+        );
+        let opts = CountOptions {
+            up_to_symmetry: true,
+            ..Default::default()
+        };
+        assert_eq!(count_packings(&domino, 4, 2, &[4], opts).unwrap().count, 4);
+        let opts = CountOptions {
+            cap: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            count_packings(&domino, 4, 2, &[4], opts),
+            Ok(PackingCount {
+                count: 3,
+                capped: true
+            })
+        );
+        // exactly as many packings as the cap
+        let opts = CountOptions {
+            cap: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(
+            count_packings(&domino, 4, 2, &[4], opts),
+            Ok(PackingCount {
+                count: 5,
+                capped: false
+            })
+        );
+        let opts = CountOptions {
+            cap: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            count_packings(&domino, 4, 2, &[4], opts),
+            Ok(PackingCount {
+                count: 0,
+                capped: true
+            })
+        );
+    }
+
+    #[test]
+    fn count_with_gaps() {
+        // a single domino in a 2x2 region, the other two cells stay empty
+        let domino = [shape("##")];
+        assert_eq!(
+            count_packings(&domino, 2, 2, &[1], CountOptions::default())
+                .unwrap()
+                .count,
+            4
+        );
+        // too many cells requested
+        assert_eq!(
+            count_packings(&domino, 2, 2, &[3], CountOptions::default())
+                .unwrap()
+                .count,
+            0
+        );
+        // shapes past the last one are fine as long as none is requested
+        assert_eq!(
+            count_packings(&domino, 2, 2, &[1, 0], CountOptions::default())
+                .unwrap()
+                .count,
+            4
+        );
+        assert_eq!(
+            count_packings(&domino, 2, 2, &[1, 1], CountOptions::default()),
+            Err(PackError::UnknownShape {
+                shape: 1,
+                shapes: 1
+            })
+        );
+    }
+
+    #[test]
+    fn node_budget() {
+        let (shapes, regions) = parse(EXAMPLE);
+        let budget = Budget {
+            max_nodes: Some(10),
+            max_time: None,
+        };
+        assert_eq!(solve_region(&shapes, &regions[2], budget), Ok(Fit::Unknown));
+        assert_eq!(
+            solve_regions(&shapes, &regions, PART1_BUDGET, 2),
+            Ok(vec![Fit::Fits, Fit::Fits, Fit::DoesNotFit])
+        );
+        // callers can still opt into a time limit, an elapsed one stops the search
+        let budget = Budget {
+            max_nodes: None,
+            max_time: Some(Duration::ZERO),
+        };
+        assert_eq!(solve_region(&shapes, &regions[2], budget), Ok(Fit::Unknown));
+    }
+
+    #[test]
+    fn count_pentomino_tilings() {
+        let shapes = pentominoes();
+        let all = [1; 12];
+        let sym = CountOptions {
+            up_to_symmetry: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            count_packings(&shapes, 20, 3, &all, CountOptions::default())
+                .unwrap()
+                .count,
+            8
+        );
+        assert_eq!(count_packings(&shapes, 20, 3, &all, sym).unwrap().count, 2);
+        assert_eq!(
+            count_packings(&shapes, 15, 4, &all, sym).unwrap().count,
+            368
+        );
+    }
 }
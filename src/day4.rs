@@ -1,6 +1,8 @@
 use aoc_runner_derive::{aoc, aoc_generator};
 use aoc_utils::{grid::Grid, point::Point};
 
+use crate::automaton::{Automaton, Neighbourhood, Rule, Update};
+
 type Input = Grid<u8>;
 
 #[aoc_generator(day4)]
@@ -8,74 +10,228 @@ pub fn parse(input: &str) -> Input {
     Grid::parse(input)
 }
 
+fn neighbours(grid: &Grid<u8>, p: Point) -> impl Iterator<Item = Point> + '_ {
+    (-1..=1)
+        .flat_map(|x| (-1..=1).map(move |y| Point::new(x, y)))
+        .filter(|&d| d != Point::new(0, 0))
+        .map(move |d| p + d)
+        .filter(|&p2| grid.contains(p2) && grid[p2] == b'@')
+}
+
+/// The forklift rule: rolls with fewer than 4 rolls around them are removed.
+pub fn forklift(update: Update) -> Automaton {
+    Automaton::new(Rule {
+        neighbourhood: Neighbourhood::Moore(1),
+        birth: 0..0,
+        survival: 4..9,
+        update,
+        alive: b'@',
+        dead: b'.',
+    })
+}
+
 #[aoc(day4, part1)]
 pub fn part1(input: &Input) -> usize {
+    forklift(Update::Synchronous).changes(input).deaths
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wave {
+    Empty,
+    /// never removed
+    Core,
+    /// removed in this round, starting from 0
+    Removed(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Waves {
+    pub grid: Grid<Wave>,
+    /// number of rolls removed in each round
+    pub counts: Vec<usize>,
+}
+
+/// Peels the rolls like a k-core, with the same rounds as removing every accessible roll at
+/// once: neighbour counts are computed once, and a roll joins the next round's frontier as
+/// soon as its count drops below 4.
+pub fn removal_waves(input: &Input) -> Waves {
     let grid = input;
-    let mut res = 0;
+    let mut counts = Grid {
+        width: grid.width,
+        height: grid.height,
+        bytes: vec![0u8; grid.bytes.len()],
+    };
+    let mut waves = Grid {
+        width: grid.width,
+        height: grid.height,
+        bytes: vec![Wave::Empty; grid.bytes.len()],
+    };
+    let mut frontier = Vec::new();
     for p in grid.iter() {
         if grid[p] != b'@' {
             continue;
         }
-        let mut rolls = 0;
-        for x in -1..=1 {
-            for y in -1..=1 {
-                if x == 0 && y == 0 {
+        counts[p] = neighbours(grid, p).count() as u8;
+        if counts[p] < 4 {
+            waves[p] = Wave::Removed(0);
+            frontier.push(p);
+        } else {
+            waves[p] = Wave::Core;
+        }
+    }
+
+    let mut round_counts = Vec::new();
+    let mut next = Vec::new();
+    while !frontier.is_empty() {
+        let round = round_counts.len();
+        round_counts.push(frontier.len());
+        for &p in &frontier {
+            for p2 in neighbours(grid, p) {
+                if waves[p2] != Wave::Core {
                     continue;
                 }
-                let p2 = p + Point::new(x, y);
-                if grid.contains(p2) && grid[p2] == b'@' {
-                    rolls += 1;
+                counts[p2] -= 1;
+                if counts[p2] < 4 {
+                    waves[p2] = Wave::Removed(round + 1);
+                    next.push(p2);
                 }
             }
         }
-        if rolls < 4 {
-            res += 1;
-        }
+        std::mem::swap(&mut frontier, &mut next);
+        next.clear();
+    }
+    Waves {
+        grid: waves,
+        counts: round_counts,
     }
-    res
 }
 
 #[aoc(day4, part2)]
 pub fn part2(input: &Input) -> usize {
-    let mut grid = input.clone();
-    let mut res = 0;
-    let mut removed = true;
-    let mut to_remove = Vec::with_capacity(grid.bytes.len());
-    while removed {
-        removed = false;
-        to_remove.clear();
+    removal_waves(input).counts.iter().sum()
+}
+
+/// Same answer as the peeling, updating in place converges in fewer sweeps.
+#[aoc(day4, part2, Automaton)]
+pub fn part2_automaton(input: &Input) -> usize {
+    forklift(Update::Asynchronous)
+        .run_to_fixpoint(&mut input.clone())
+        .1
+        .deaths
+}
+
+/// Rolls packed in bits, each row taking `words` u64 with column x at bit x % 64 of word x / 64.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitboard {
+    width: usize,
+    height: usize,
+    words: usize,
+    rows: Vec<u64>,
+}
+
+// adds a one bit number to each lane of a bit-sliced 4 bit counter
+fn add_bit(counter: &mut [u64; 4], mut carry: u64) {
+    for bit in counter {
+        let next = *bit & carry;
+        *bit ^= carry;
+        carry = next;
+    }
+}
+
+impl Bitboard {
+    pub fn new(grid: &Grid<u8>) -> Self {
+        let words = grid.width.div_ceil(64);
+        let mut rows = vec![0; words * grid.height];
         for p in grid.iter() {
-            if grid[p] != b'@' {
-                continue;
+            if grid[p] == b'@' {
+                let (x, y) = (p.x as usize, p.y as usize);
+                rows[y * words + x / 64] |= 1 << (x % 64);
             }
-            let mut rolls = 0;
-            for x2 in -1..=1 {
-                for y2 in -1..=1 {
-                    if x2 == 0 && y2 == 0 {
-                        continue;
-                    }
-                    let p2 = p + Point::new(x2, y2);
-                    if grid.contains(p2) && grid[p2] == b'@' {
-                        rolls += 1;
+        }
+        Bitboard {
+            width: grid.width,
+            height: grid.height,
+            words,
+            rows,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.rows.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|&w| w == 0)
+    }
+
+    fn word(&self, y: usize, j: usize) -> u64 {
+        self.rows[y * self.words + j]
+    }
+
+    /// Rolls with fewer than 4 rolls around them, 64 cells at a time.
+    pub fn accessible(&self) -> Bitboard {
+        let mut res = Bitboard {
+            rows: vec![0; self.rows.len()],
+            ..*self
+        };
+        for y in 0..self.height {
+            for j in 0..self.words {
+                let mut counter = [0; 4];
+                let ys = y.saturating_sub(1)..(y + 2).min(self.height);
+                for y2 in ys {
+                    let word = self.word(y2, j);
+                    let before = if j > 0 { self.word(y2, j - 1) } else { 0 };
+                    let after = if j + 1 < self.words {
+                        self.word(y2, j + 1)
+                    } else {
+                        0
+                    };
+                    // neighbours on the left and on the right of each cell
+                    add_bit(&mut counter, (word << 1) | (before >> 63));
+                    add_bit(&mut counter, (word >> 1) | (after << 63));
+                    if y2 != y {
+                        add_bit(&mut counter, word);
                     }
                 }
+                // at most 8 neighbours, so fewer than 4 means no bit for 4 or 8
+                res.rows[y * self.words + j] = self.word(y, j) & !(counter[2] | counter[3]);
             }
-            if rolls < 4 {
-                to_remove.push(p);
-                res += 1;
-            }
         }
-        for p in &to_remove {
-            grid[*p] = b'.';
-            removed = true;
+        res
+    }
+
+    pub fn remove(&mut self, other: &Bitboard) {
+        for (w, o) in self.rows.iter_mut().zip(&other.rows) {
+            *w &= !o;
+        }
+    }
+}
+
+#[aoc(day4, part1, Bitboard)]
+pub fn part1_bitboard(input: &Input) -> usize {
+    Bitboard::new(input).accessible().count()
+}
+
+/// Removes the accessible rolls round by round, which is fast enough with whole words.
+#[aoc(day4, part2, Bitboard)]
+pub fn part2_bitboard(input: &Input) -> usize {
+    let mut board = Bitboard::new(input);
+    let mut res = 0;
+    loop {
+        let accessible = board.accessible();
+        if accessible.is_empty() {
+            return res;
         }
+        res += accessible.count();
+        board.remove(&accessible);
     }
-    res
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::rng::Rng;
+    use std::time::Instant;
 
     const EXAMPLE: &str = r#"..@@.@@@@.
 @@@.@.@.@@
@@ -91,10 +247,149 @@ mod test {
     #[test]
     fn part1_example() {
         assert_eq!(part1(&parse(EXAMPLE)), 13);
+        assert_eq!(part1_bitboard(&parse(EXAMPLE)), 13);
     }
 
     #[test]
     fn part2_example() {
         assert_eq!(part2(&parse(EXAMPLE)), 43);
+        assert_eq!(part2_bitboard(&parse(EXAMPLE)), 43);
+        assert_eq!(part2_automaton(&parse(EXAMPLE)), 43);
+    }
+
+    // the original implementation, removing rolls round by round
+    fn part2_rounds(input: &Input) -> usize {
+        let mut grid = input.clone();
+        let mut res = 0;
+        let mut removed = true;
+        let mut to_remove = Vec::with_capacity(grid.bytes.len());
+        while removed {
+            removed = false;
+            to_remove.clear();
+            for p in grid.iter() {
+                if grid[p] != b'@' {
+                    continue;
+                }
+                let mut rolls = 0;
+                for x2 in -1..=1 {
+                    for y2 in -1..=1 {
+                        if x2 == 0 && y2 == 0 {
+                            continue;
+                        }
+                        let p2 = p + Point::new(x2, y2);
+                        if grid.contains(p2) && grid[p2] == b'@' {
+                            rolls += 1;
+                        }
+                    }
+                }
+                if rolls < 4 {
+                    to_remove.push(p);
+                    res += 1;
+                }
+            }
+            for p in &to_remove {
+                grid[*p] = b'.';
+                removed = true;
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn waves() {
+        let waves = removal_waves(&parse(EXAMPLE));
+        assert_eq!(waves.counts, [13, 12, 7, 5, 2, 1, 1, 1, 1]);
+        assert_eq!(waves.grid[Point::new(0, 0)], Wave::Empty);
+        assert_eq!(waves.grid[Point::new(2, 0)], Wave::Removed(0));
+        assert_eq!(waves.grid[Point::new(4, 4)], Wave::Core);
+
+        // the same rounds as the automaton
+        let rule = forklift(Update::Synchronous);
+        let mut rng = Rng::new(50);
+        for _ in 0..50 {
+            let width = 1 + rng.below(30) as usize;
+            let height = 1 + rng.below(30) as usize;
+            let mut grid = parse(&random_grid(&mut rng, width, height));
+            let waves = removal_waves(&grid);
+            assert_eq!(part1(&grid), waves.counts.first().copied().unwrap_or(0));
+            for (round, &count) in waves.counts.iter().enumerate() {
+                let before = grid.clone();
+                assert_eq!(rule.step(&mut grid).deaths, count);
+                for p in grid.iter() {
+                    let removed = before[p] == b'@' && grid[p] != b'@';
+                    assert_eq!(removed, waves.grid[p] == Wave::Removed(round));
+                }
+            }
+            assert!(rule.step(&mut grid).is_empty());
+            for p in grid.iter() {
+                assert_eq!(grid[p] == b'@', waves.grid[p] == Wave::Core);
+            }
+        }
+    }
+
+    fn random_grid(rng: &mut Rng, width: usize, height: usize) -> String {
+        let density = rng.below(10);
+        (0..height)
+            .map(|_| {
+                (0..width)
+                    .map(|_| if rng.below(10) < density { '@' } else { '.' })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn peeling_matches_rounds() {
+        assert_eq!(part2_rounds(&parse(EXAMPLE)), 43);
+        let mut rng = Rng::new(4);
+        for _ in 0..100 {
+            let width = 1 + rng.below(30) as usize;
+            let height = 1 + rng.below(30) as usize;
+            let input = parse(&random_grid(&mut rng, width, height));
+            assert_eq!(part2(&input), part2_rounds(&input));
+            assert_eq!(part2_automaton(&input), part2_rounds(&input));
+        }
+    }
+
+    #[test]
+    fn bitboard_matches_grid() {
+        let mut rng = Rng::new(48);
+        for _ in 0..100 {
+            // widths around the word boundaries
+            let width = 1 + rng.below(200) as usize;
+            let height = 1 + rng.below(20) as usize;
+            let input = parse(&random_grid(&mut rng, width, height));
+            assert_eq!(part1_bitboard(&input), part1(&input));
+            assert_eq!(part2_bitboard(&input), part2(&input));
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_large_grid() {
+        // cargo test --release bench_large_grid -- --ignored
+        let mut rng = Rng::new(42);
+        let input = parse(&random_grid(&mut rng, 2000, 2000));
+        let time = |solve: fn(&Input) -> usize| {
+            let start = Instant::now();
+            (solve(&input), start.elapsed())
+        };
+        for (part, grid, bitboard) in [
+            (
+                "part1",
+                part1 as fn(&Input) -> usize,
+                part1_bitboard as fn(&Input) -> usize,
+            ),
+            ("part2", part2, part2_bitboard),
+        ] {
+            let (expected, grid_time) = time(grid);
+            let (res, bitboard_time) = time(bitboard);
+            assert_eq!(res, expected);
+            assert!(
+                bitboard_time < grid_time,
+                "{part}: bitboard {bitboard_time:?}, grid {grid_time:?}"
+            );
+        }
     }
 }
@@ -1,11 +1,16 @@
-use std::collections::VecDeque;
+use std::{
+    fmt,
+    io::{self, BufRead},
+};
 
 use aoc_runner_derive::{aoc, aoc_generator};
 use aoc_utils::{
     grid::Grid,
-    point::{DOWN, LEFT, Point, RIGHT},
+    point::{DOWN, LEFT, Point, RIGHT, UP},
 };
 
+use crate::rng::Rng;
+
 type Input = (Grid<u8>, Point);
 
 #[aoc_generator(day7)]
@@ -15,108 +20,812 @@ pub fn parse(input: &str) -> Input {
     (grid, start)
 }
 
-#[aoc(day7, part1)]
-pub fn part1(input: &Input) -> usize {
-    let (grid, start) = input;
-    let mut queue = vec![*start];
-    let mut res = 0;
-    let mut seen = Grid {
-        width: grid.width,
-        height: grid.height,
-        bytes: vec![false; grid.width * grid.height],
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Beam {
+    pub pos: Point,
+    pub dir: Point,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimelineError {
+    /// A beam went through the same cell in the same direction twice,
+    /// so there are infinitely many timelines.
+    Loop { beam: Beam },
+    /// Too many timelines for the count type.
+    Overflow,
+    /// A splitter can't send a beam left with a probability above 1.
+    InvalidProbability { p: Ratio },
+}
+
+impl fmt::Display for TimelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimelineError::Loop { beam } => write!(
+                f,
+                "beam at {},{} going {},{} loops",
+                beam.pos.x, beam.pos.y, beam.dir.x, beam.dir.y
+            ),
+            TimelineError::Overflow => write!(f, "too many timelines"),
+            TimelineError::InvalidProbability { p } => write!(f, "probability {p} above 1"),
+        }
+    }
+}
+
+impl std::error::Error for TimelineError {}
+
+/// Number of timelines: each split doubles them, so they quickly outgrow `u64`.
+pub trait Count: Copy + PartialEq + fmt::Debug {
+    const ZERO: Self;
+    const ONE: Self;
+
+    /// `None` on overflow.
+    fn checked_add(self, other: Self) -> Option<Self>;
+
+    /// `None` on overflow.
+    fn checked_mul(self, other: Self) -> Option<Self>;
+}
+
+macro_rules! impl_count {
+    ($($t:ty),*) => {
+        $(impl Count for $t {
+            const ZERO: Self = 0;
+            const ONE: Self = 1;
+
+            fn checked_add(self, other: Self) -> Option<Self> {
+                <$t>::checked_add(self, other)
+            }
+
+            fn checked_mul(self, other: Self) -> Option<Self> {
+                <$t>::checked_mul(self, other)
+            }
+        })*
     };
-    seen[*start] = true;
+}
 
-    while let Some(pos) = queue.pop() {
-        let mut down_pos = pos + DOWN;
+impl_count!(u64, u128, usize);
 
-        while grid.contains(down_pos) {
-            if grid[down_pos] == b'^' {
-                if !seen[down_pos] {
-                    res += 1;
+/// Count modulo `P`, usually a prime like 1_000_000_007, which never overflows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModPrime<const P: u64>(pub u64);
+
+impl<const P: u64> Count for ModPrime<P> {
+    const ZERO: Self = ModPrime(0);
+    const ONE: Self = ModPrime(1 % P);
+
+    fn checked_add(self, other: Self) -> Option<Self> {
+        Some(ModPrime(
+            ((self.0 as u128 + other.0 as u128) % P as u128) as u64,
+        ))
+    }
+
+    fn checked_mul(self, other: Self) -> Option<Self> {
+        Some(ModPrime(
+            (self.0 as u128 * other.0 as u128 % P as u128) as u64,
+        ))
+    }
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Exact non-negative fraction, always reduced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ratio {
+    num: u128,
+    den: u128,
+}
+
+impl Ratio {
+    pub fn new(num: u128, den: u128) -> Self {
+        assert!(den != 0, "zero denominator");
+        let g = gcd(num, den);
+        Ratio {
+            num: num / g,
+            den: den / g,
+        }
+    }
+
+    pub fn num(&self) -> u128 {
+        self.num
+    }
+
+    pub fn den(&self) -> u128 {
+        self.den
+    }
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.num, self.den)
+    }
+}
+
+impl Count for Ratio {
+    const ZERO: Self = Ratio { num: 0, den: 1 };
+    const ONE: Self = Ratio { num: 1, den: 1 };
+
+    fn checked_add(self, other: Self) -> Option<Self> {
+        let g = gcd(self.den, other.den);
+        let den = (self.den / g).checked_mul(other.den)?;
+        let num = self
+            .num
+            .checked_mul(other.den / g)?
+            .checked_add(other.num.checked_mul(self.den / g)?)?;
+        Some(Ratio::new(num, den))
+    }
+
+    fn checked_mul(self, other: Self) -> Option<Self> {
+        // cross reduce first, the result is then reduced already
+        let (g1, g2) = (gcd(self.num, other.den), gcd(other.num, self.den));
+        Some(Ratio {
+            num: (self.num / g1).checked_mul(other.num / g2)?,
+            den: (self.den / g2).checked_mul(other.den / g1)?,
+        })
+    }
+}
+
+fn add<C: Count>(total: &mut C, c: C) -> Result<(), TimelineError> {
+    *total = total.checked_add(c).ok_or(TimelineError::Overflow)?;
+    Ok(())
+}
+
+fn mul<C: Count>(a: C, b: C) -> Result<C, TimelineError> {
+    a.checked_mul(b).ok_or(TimelineError::Overflow)
+}
+
+const DIRECTIONS: [Point; 4] = [UP, RIGHT, DOWN, LEFT];
+
+/// Beams going through a manifold, with:
+/// - `S`: a source, sending a beam down
+/// - `^`: a splitter, sending two beams on each side of it, going the same way
+/// - `/` and `\`: mirrors
+/// - `|` and `-`: splitters sending two beams out of their ends, unless coming along them
+///
+/// Beams sent on the side of a `^` ignore what's on the tile they start from,
+/// like the beams of the original puzzle.
+pub struct BeamEngine<'a> {
+    grid: &'a Grid<u8>,
+}
+
+impl<'a> BeamEngine<'a> {
+    pub fn new(grid: &'a Grid<u8>) -> Self {
+        BeamEngine { grid }
+    }
+
+    pub fn sources(&self) -> impl Iterator<Item = Beam> + '_ {
+        self.grid
+            .iter()
+            .filter(|&p| self.grid[p] == b'S')
+            .map(|pos| Beam { pos, dir: DOWN })
+    }
+
+    /// Beams after moving by one tile, the ones outside of the grid have left the manifold.
+    ///
+    /// On a split, the first beam is the one heading towards the smaller x, or the smaller y
+    /// when they agree: it's the left one for a beam going down.
+    pub fn step(&self, beam: Beam) -> [Option<Beam>; 2] {
+        let Beam { pos, dir } = beam;
+        let next = pos + dir;
+        if !self.grid.contains(next) {
+            return [Some(Beam { pos: next, dir }), None];
+        }
+        let beam = |pos, dir| Some(Beam { pos, dir });
+        let perpendicular = Point::new(dir.y.abs(), dir.x.abs());
+        match self.grid[next] {
+            b'^' => [
+                beam(next - perpendicular, dir),
+                beam(next + perpendicular, dir),
+            ],
+            b'/' => [beam(next, Point::new(-dir.y, -dir.x)), None],
+            b'\\' => [beam(next, Point::new(dir.y, dir.x)), None],
+            b'|' if dir.y == 0 => [beam(next, UP), beam(next, DOWN)],
+            b'-' if dir.x == 0 => [beam(next, LEFT), beam(next, RIGHT)],
+            _ => [beam(next, dir), None],
+        }
+    }
+
+    fn index(&self, beam: Beam) -> usize {
+        let dir = DIRECTIONS.iter().position(|&d| d == beam.dir).unwrap();
+        (beam.pos.y as usize * self.grid.width + beam.pos.x as usize) * 4 + dir
+    }
+
+    /// Number of `^` splitters reached by any beam.
+    pub fn splitters_hit(&self) -> usize {
+        let mut seen = vec![false; self.grid.width * self.grid.height * 4];
+        let mut hit = Grid {
+            width: self.grid.width,
+            height: self.grid.height,
+            bytes: vec![false; self.grid.width * self.grid.height],
+        };
+        let mut res = 0;
+        let mut stack: Vec<Beam> = self.sources().collect();
+        for &beam in &stack {
+            seen[self.index(beam)] = true;
+        }
+        while let Some(beam) = stack.pop() {
+            let next = beam.pos + beam.dir;
+            if self.grid.contains(next) && self.grid[next] == b'^' && !hit[next] {
+                hit[next] = true;
+                res += 1;
+            }
+            for b in self.step(beam).into_iter().flatten() {
+                if self.grid.contains(b.pos) && !seen[self.index(b)] {
+                    seen[self.index(b)] = true;
+                    stack.push(b);
                 }
-                seen[down_pos] = true;
+            }
+        }
+        res
+    }
 
-                let p1 = down_pos + LEFT;
-                let p2 = down_pos + RIGHT;
-                if !seen[p1] && grid.contains(p1) {
-                    seen[p1] = true;
-                    queue.push(p1);
+    /// Number of ways out of the manifold over every source, each split doubling the
+    /// timeline it happens in.
+    pub fn timelines<C: Count>(&self) -> Result<C, TimelineError> {
+        Ok(self.ways()?.1)
+    }
+
+    /// Every beam reachable from a source, each one after all the beams it leads to.
+    fn postorder(&self) -> Result<Vec<Beam>, TimelineError> {
+        const ACTIVE: u8 = 1;
+        const DONE: u8 = 2;
+        let mut state = vec![0u8; self.grid.width * self.grid.height * 4];
+        let mut order = Vec::new();
+        for source in self.sources() {
+            // iterative DFS, so that tall manifolds don't overflow the stack
+            let mut stack = vec![(source, false)];
+            while let Some((beam, expanded)) = stack.pop() {
+                let idx = self.index(beam);
+                if expanded {
+                    order.push(beam);
+                    state[idx] = DONE;
+                    continue;
                 }
-                if !seen[p2] && grid.contains(p2) {
-                    seen[p2] = true;
-                    queue.push(p2);
+                match state[idx] {
+                    DONE => continue,
+                    // still waiting on its own successors
+                    ACTIVE => return Err(TimelineError::Loop { beam }),
+                    _ => {}
                 }
+                state[idx] = ACTIVE;
+                stack.push((beam, true));
+                for b in self.step(beam).into_iter().flatten() {
+                    if self.grid.contains(b.pos) && state[self.index(b)] != DONE {
+                        stack.push((b, false));
+                    }
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    /// Number of timelines from every beam reachable from a source, and the total.
+    fn ways<C: Count>(&self) -> Result<(Vec<C>, C), TimelineError> {
+        let mut ways = vec![C::ZERO; self.grid.width * self.grid.height * 4];
+        for beam in self.postorder()? {
+            let mut total = C::ZERO;
+            for b in self.step(beam).into_iter().flatten() {
+                if self.grid.contains(b.pos) {
+                    add(&mut total, ways[self.index(b)])?;
+                } else {
+                    add(&mut total, C::ONE)?;
+                }
+            }
+            ways[self.index(beam)] = total;
+        }
+        let mut res = C::ZERO;
+        for source in self.sources() {
+            add(&mut res, ways[self.index(source)])?;
+        }
+        Ok((ways, res))
+    }
+
+    /// Where the timelines leave the manifold, following them from the sources. On a split,
+    /// the first beam gets `split.0` times the incoming count and the second one `split.1` times.
+    fn exits<C: Count>(&self, split: (C, C)) -> Result<Exits<C>, TimelineError> {
+        let mut flow = vec![C::ZERO; self.grid.width * self.grid.height * 4];
+        for source in self.sources() {
+            add(&mut flow[self.index(source)], C::ONE)?;
+        }
+        let mut exits = Exits {
+            columns: vec![C::ZERO; self.grid.width],
+            top: C::ZERO,
+            left: C::ZERO,
+            right: C::ZERO,
+        };
+        for beam in self.postorder()?.into_iter().rev() {
+            let c = flow[self.index(beam)];
+            if c == C::ZERO {
+                continue;
+            }
+            let next = self.step(beam);
+            let weights = match next {
+                [Some(_), Some(_)] => [split.0, split.1],
+                _ => [C::ONE, C::ONE],
+            };
+            for (b, w) in next.into_iter().zip(weights) {
+                let Some(b) = b else { continue };
+                let c = mul(c, w)?;
+                let Point { x, y } = b.pos;
+                let slot = if self.grid.contains(b.pos) {
+                    &mut flow[self.index(b)]
+                } else if y >= self.grid.height as i64 {
+                    &mut exits.columns[x as usize]
+                } else if y < 0 {
+                    &mut exits.top
+                } else if x < 0 {
+                    &mut exits.left
+                } else {
+                    &mut exits.right
+                };
+                add(slot, c)?;
+            }
+        }
+        Ok(exits)
+    }
+
+    /// Every timeline, one by one and up to `cap` of them, taking the left side of a split first,
+    /// or a random one if there is a `seed`.
+    pub fn enumerate_timelines(
+        &self,
+        cap: usize,
+        seed: Option<u64>,
+    ) -> Result<impl Iterator<Item = Timeline> + '_, TimelineError> {
+        // a loop would never end, the counts are only needed to find them
+        self.timelines::<ModPrime<1_000_000_007>>()?;
+        let mut stack: Vec<(Beam, Vec<Side>)> = self.sources().map(|b| (b, Vec::new())).collect();
+        // the first source comes out first
+        stack.reverse();
+        let mut rng = seed.map(Rng::new);
+        let timelines = std::iter::from_fn(move || {
+            while let Some((beam, mut choices)) = stack.pop() {
+                if !self.grid.contains(beam.pos) {
+                    return Some(Timeline {
+                        choices,
+                        exit: beam,
+                    });
+                }
+                match self.step(beam) {
+                    [Some(left), Some(right)] => {
+                        let mut sides = [(right, Side::Right), (left, Side::Left)];
+                        if let Some(rng) = &mut rng
+                            && rng.below(2) == 0
+                        {
+                            sides.swap(0, 1);
+                        }
+                        let mut other = choices.clone();
+                        other.push(sides[0].1);
+                        choices.push(sides[1].1);
+                        stack.push((sides[0].0, other));
+                        stack.push((sides[1].0, choices));
+                    }
+                    [Some(next), None] => stack.push((next, choices)),
+                    _ => unreachable!(),
+                }
+            }
+            None
+        });
+        Ok(timelines.take(cap))
+    }
+
+    /// Random timelines, every one of them being as likely.
+    pub fn sample_timelines(&self, seed: u64) -> Result<TimelineSampler<'_, 'a>, TimelineError> {
+        let (ways, total) = self.ways::<u128>()?;
+        Ok(TimelineSampler {
+            engine: self,
+            ways,
+            total,
+            rng: Rng::new(seed),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// One way through the manifold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timeline {
+    /// The side taken at every split, in order.
+    pub choices: Vec<Side>,
+    /// The beam once it left the manifold.
+    pub exit: Beam,
+}
+
+impl fmt::Display for Timeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for side in &self.choices {
+            let c = match side {
+                Side::Left => 'L',
+                Side::Right => 'R',
+            };
+            write!(f, "{c}")?;
+        }
+        write!(f, " -> {},{}", self.exit.pos.x, self.exit.pos.y)
+    }
+}
+
+/// Infinite iterator over random timelines, see `BeamEngine::sample_timelines`.
+pub struct TimelineSampler<'e, 'a> {
+    engine: &'e BeamEngine<'a>,
+    ways: Vec<u128>,
+    total: u128,
+    rng: Rng,
+}
+
+impl TimelineSampler<'_, '_> {
+    fn weight(&self, beam: Beam) -> u128 {
+        if self.engine.grid.contains(beam.pos) {
+            self.ways[self.engine.index(beam)]
+        } else {
+            1
+        }
+    }
+}
+
+impl Iterator for TimelineSampler<'_, '_> {
+    type Item = Timeline;
+
+    fn next(&mut self) -> Option<Timeline> {
+        if self.total == 0 {
+            return None;
+        }
+        // pick a source, then a side at every split, weighted by how many timelines follow
+        let mut r = self.rng.below_u128(self.total);
+        let mut beam = None;
+        for source in self.engine.sources() {
+            let w = self.weight(source);
+            if r < w {
+                beam = Some(source);
                 break;
             }
-            seen[down_pos] = true;
-            down_pos += DOWN;
+            r -= w;
+        }
+        let mut beam = beam.unwrap();
+        let mut choices = Vec::new();
+        while self.engine.grid.contains(beam.pos) {
+            beam = match self.engine.step(beam) {
+                [Some(left), Some(right)] => {
+                    let w = self.weight(left);
+                    if self.rng.below_u128(w + self.weight(right)) < w {
+                        choices.push(Side::Left);
+                        left
+                    } else {
+                        choices.push(Side::Right);
+                        right
+                    }
+                }
+                [Some(next), None] => next,
+                _ => unreachable!(),
+            };
         }
+        Some(Timeline {
+            choices,
+            exit: beam,
+        })
     }
-    res
 }
 
-#[aoc(day7, part2)]
-pub fn part2(input: &Input) -> usize {
-    let (grid, start) = input;
-    let mut queue = VecDeque::new();
-    queue.push_back(*start);
-    let mut seen = Grid {
-        width: grid.width,
-        height: grid.height,
-        bytes: vec![false; grid.width * grid.height],
-    };
-    seen[*start] = true;
+/// Where a beam goes in a [`SplitterDag`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Splitter(usize),
+    /// leaving the manifold, from this position outside of the grid
+    Exit(Point),
+}
 
-    let mut count = Grid {
-        width: grid.width,
-        height: grid.height,
-        bytes: vec![0usize; grid.width * grid.height],
-    };
-    count[*start] = 1;
-
-    while let Some(pos) = queue.pop_front() {
-        let base_val = count[pos];
-        let down_pos = pos + DOWN;
-        if !grid.contains(down_pos) {
-            continue;
-        }
-
-        // NOTE: we cannot use a while loop like in part1,
-        // because we need to propagate counts correctly
-        // we go 'level by level' in a BFS style
-        if grid[down_pos] == b'^' {
-            let p1 = down_pos + LEFT;
-            let p2 = down_pos + RIGHT;
-            count[p1] += base_val;
-            count[p2] += base_val;
-            if !seen[p1] {
-                seen[p1] = true;
-                queue.push_back(p1);
-            }
-            if !seen[p2] {
-                seen[p2] = true;
-                queue.push_back(p2);
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitterNode {
+    pub pos: Point,
+    pub left: Target,
+    pub right: Target,
+    pub never_hit: bool,
+}
+
+/// The `^` splitters of a manifold, each one feeding the next splitter below it on each
+/// side. Nodes are in reading order, so every edge goes to a later node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitterDag {
+    pub nodes: Vec<SplitterNode>,
+    /// where the beam of each source goes first
+    pub roots: Vec<Target>,
+}
+
+impl SplitterDag {
+    /// `None` if the manifold has anything other than sources and `^` splitters.
+    pub fn new(grid: &Grid<u8>) -> Option<Self> {
+        if grid.bytes.iter().any(|b| !matches!(b, b'.' | b'S' | b'^')) {
+            return None;
+        }
+        let (width, height) = (grid.width as i64, grid.height as i64);
+        let placeholder = SplitterNode {
+            pos: Point::new(0, 0),
+            left: Target::Exit(Point::new(0, 0)),
+            right: Target::Exit(Point::new(0, 0)),
+            never_hit: true,
+        };
+        let mut nodes = vec![placeholder; grid.bytes.iter().filter(|&&b| b == b'^').count()];
+        let mut roots = Vec::new();
+
+        // first target going down from each column, for the rows below the current one
+        let mut below: Vec<Target> = (0..width)
+            .map(|x| Target::Exit(Point::new(x, height)))
+            .collect();
+        let mut id = nodes.len();
+        for y in (0..height).rev() {
+            let side = |x: i64| match x {
+                0.. if x < width => below[x as usize],
+                _ => Target::Exit(Point::new(x, y)),
+            };
+            let mut row_splitters = Vec::new();
+            for x in (0..width).rev() {
+                let pos = Point::new(x, y);
+                match grid[pos] {
+                    b'^' => {
+                        id -= 1;
+                        nodes[id] = SplitterNode {
+                            pos,
+                            left: side(x - 1),
+                            right: side(x + 1),
+                            never_hit: true,
+                        };
+                        row_splitters.push((x, id));
+                    }
+                    b'S' => roots.push(side(x)),
+                    _ => {}
+                }
             }
-        } else {
-            count[down_pos] += base_val;
-            if !seen[down_pos] {
-                queue.push_back(down_pos);
+            for (x, id) in row_splitters {
+                below[x as usize] = Target::Splitter(id);
+            }
+        }
+        roots.reverse();
+
+        for &root in &roots {
+            if let Target::Splitter(i) = root {
+                nodes[i].never_hit = false;
             }
-            seen[down_pos] = true;
         }
+        for i in 0..nodes.len() {
+            if nodes[i].never_hit {
+                continue;
+            }
+            for target in [nodes[i].left, nodes[i].right] {
+                if let Target::Splitter(j) = target {
+                    nodes[j].never_hit = false;
+                }
+            }
+        }
+        Some(SplitterDag { nodes, roots })
     }
-    let mut res = 0;
-    for x in 0..grid.width {
-        let p = Point::new(x as i64, (grid.height - 1) as i64);
-        res += count[p];
+
+    /// Number of splitters hit by a beam.
+    pub fn reachable(&self) -> usize {
+        self.nodes.iter().filter(|node| !node.never_hit).count()
+    }
+
+    /// Number of paths from the sources to an exit, the timelines of part 2.
+    pub fn paths<C: Count>(&self) -> Result<C, TimelineError> {
+        let mut ways = vec![C::ZERO; self.nodes.len()];
+        let count = |ways: &[C], target| match target {
+            Target::Splitter(i) => ways[i],
+            Target::Exit(_) => C::ONE,
+        };
+        for i in (0..self.nodes.len()).rev() {
+            let mut total = count(&ways, self.nodes[i].left);
+            add(&mut total, count(&ways, self.nodes[i].right))?;
+            ways[i] = total;
+        }
+        let mut total = C::ZERO;
+        for &root in &self.roots {
+            add(&mut total, count(&ways, root))?;
+        }
+        Ok(total)
+    }
+
+    /// Graphviz export, with a single `exit` node and dashed splitters for the ones never hit.
+    pub fn to_dot(&self) -> String {
+        let name = |target| match target {
+            Target::Splitter(i) => format!("n{i}"),
+            Target::Exit(_) => "exit".to_string(),
+        };
+        let mut out = String::from("digraph splitters {\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let style = if node.never_hit { ", style=dashed" } else { "" };
+            out += &format!("  n{i} [label=\"{},{}\"{style}];\n", node.pos.x, node.pos.y);
+        }
+        out += "  exit [shape=doublecircle];\n";
+        for (i, &root) in self.roots.iter().enumerate() {
+            out += &format!("  s{i} [shape=point];\n  s{i} -> {};\n", name(root));
+        }
+        for (i, node) in self.nodes.iter().enumerate() {
+            out += &format!("  n{i} -> {} [label=L];\n", name(node.left));
+            out += &format!("  n{i} -> {} [label=R];\n", name(node.right));
+        }
+        out + "}\n"
+    }
+
+    /// JSON export, with splitters as indices into `nodes` and exits as `{"exit":[x,y]}`.
+    pub fn to_json(&self) -> String {
+        let target = |target| match target {
+            Target::Splitter(i) => i.to_string(),
+            Target::Exit(p) => format!("{{\"exit\":[{},{}]}}", p.x, p.y),
+        };
+        let nodes: Vec<String> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                format!(
+                    "{{\"x\":{},\"y\":{},\"left\":{},\"right\":{},\"never_hit\":{}}}",
+                    node.pos.x,
+                    node.pos.y,
+                    target(node.left),
+                    target(node.right),
+                    node.never_hit
+                )
+            })
+            .collect();
+        let roots: Vec<String> = self.roots.iter().map(|&root| target(root)).collect();
+        format!(
+            "{{\"nodes\":[{}],\"roots\":[{}]}}",
+            nodes.join(","),
+            roots.join(",")
+        )
+    }
+}
+
+#[aoc(day7, part1)]
+pub fn part1(input: &Input) -> usize {
+    match SplitterDag::new(&input.0) {
+        Some(dag) => dag.reachable(),
+        None => BeamEngine::new(&input.0).splitters_hit(),
+    }
+}
+
+/// Where the beams leave the manifold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exits<C> {
+    /// Through the bottom of each column.
+    pub columns: Vec<C>,
+    /// Through the top and the sides.
+    pub top: C,
+    pub left: C,
+    pub right: C,
+}
+
+impl<C: Count> Exits<C> {
+    pub fn total(&self) -> Result<C, TimelineError> {
+        let mut res = self.top;
+        add(&mut res, self.left)?;
+        add(&mut res, self.right)?;
+        for &c in &self.columns {
+            add(&mut res, c)?;
+        }
+        Ok(res)
+    }
+}
+
+/// Number of timelines per column, fed one row at a time: beams only ever go down,
+/// so the previous row is all that is needed.
+///
+/// Only the timelines leaving through the bottom are counted: beams leaving through the
+/// sides are lost, while `part2` counts them too. `exit_histogram` has both.
+#[derive(Debug, Clone)]
+pub struct BottomExitDp<C = usize> {
+    counts: Vec<C>,
+    next: Vec<C>,
+}
+
+impl<C: Count> Default for BottomExitDp<C> {
+    fn default() -> Self {
+        BottomExitDp {
+            counts: Vec::new(),
+            next: Vec::new(),
+        }
+    }
+}
+
+impl<C: Count> BottomExitDp<C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the beams to the next row. Rows before the one with `S` are skipped.
+    pub fn push_row(&mut self, row: &[u8]) -> Result<(), TimelineError> {
+        if self.counts.is_empty() {
+            if let Some(x) = row.iter().position(|&c| c == b'S') {
+                self.counts = vec![C::ZERO; row.len()];
+                self.counts[x] = C::ONE;
+            }
+            return Ok(());
+        }
+        let width = self.counts.len();
+        self.next.clear();
+        self.next.resize(width, C::ZERO);
+        for (x, &c) in self.counts.iter().enumerate() {
+            if c == C::ZERO {
+                continue;
+            }
+            if row.get(x) == Some(&b'^') {
+                // beams going out of the manifold are lost
+                if x > 0 {
+                    add(&mut self.next[x - 1], c)?;
+                }
+                if x + 1 < width {
+                    add(&mut self.next[x + 1], c)?;
+                }
+            } else {
+                add(&mut self.next[x], c)?;
+            }
+        }
+        std::mem::swap(&mut self.counts, &mut self.next);
+        Ok(())
+    }
+
+    /// Timelines ending in each column of the last row pushed.
+    pub fn counts(&self) -> &[C] {
+        &self.counts
+    }
+
+    /// Timelines leaving through the bottom, taking the last row pushed as the bottom.
+    pub fn bottom_exits(&self) -> Result<C, TimelineError> {
+        let mut res = C::ZERO;
+        for &c in &self.counts {
+            add(&mut res, c)?;
+        }
+        Ok(res)
+    }
+}
+
+/// Number of timelines leaving the manifold at each place, adding up to `part2`.
+pub fn exit_histogram<C: Count>(grid: &Grid<u8>) -> Result<Exits<C>, TimelineError> {
+    BeamEngine::new(grid).exits((C::ONE, C::ONE))
+}
+
+/// Probability for the beam of each source to leave the manifold at each place, when splitters
+/// send it to the left with probability `p` and to the right otherwise. For `|` and `-`,
+/// `p` goes to the beam heading up or left.
+pub fn exit_probabilities(grid: &Grid<u8>, p: Ratio) -> Result<Exits<Ratio>, TimelineError> {
+    if p.num > p.den {
+        return Err(TimelineError::InvalidProbability { p });
+    }
+    BeamEngine::new(grid).exits((p, Ratio::new(p.den - p.num, p.den)))
+}
+
+/// Counts the timelines leaving through the bottom while reading the manifold, only keeping
+/// two rows worth of counts. The same as `part2` when no beam leaves through the sides.
+pub fn count_bottom_exits<C: Count>(mut reader: impl BufRead) -> io::Result<C> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut dp = BottomExitDp::new();
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        while line.last().is_some_and(|c| c.is_ascii_whitespace()) {
+            line.pop();
+        }
+        dp.push_row(&line).map_err(invalid)?;
+        line.clear();
+    }
+    dp.bottom_exits().map_err(invalid)
+}
+
+#[aoc(day7, part2)]
+pub fn part2(input: &Input) -> Result<usize, TimelineError> {
+    match SplitterDag::new(&input.0) {
+        Some(dag) => dag.paths(),
+        None => BeamEngine::new(&input.0).timelines(),
     }
-    res
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::collections::VecDeque;
 
     const EXAMPLE: &str = r#".......S.......
 ...............
@@ -142,6 +851,510 @@ mod test {
 
     #[test]
     fn part2_example() {
-        assert_eq!(part2(&parse(EXAMPLE)), 40);
+        assert_eq!(part2(&parse(EXAMPLE)), Ok(40));
+        assert_eq!(count_bottom_exits::<usize>(EXAMPLE.as_bytes()).unwrap(), 40);
+        // rows before the start and CRLF line endings
+        let crlf = format!("...............\r\n{}\r\n", EXAMPLE.replace('\n', "\r\n"));
+        assert_eq!(count_bottom_exits::<usize>(crlf.as_bytes()).unwrap(), 40);
+    }
+
+    // the original BFS over the whole grid, as a reference
+    fn part2_bfs(input: &Input) -> usize {
+        let (grid, start) = input;
+        let mut queue = VecDeque::new();
+        queue.push_back(*start);
+        let mut seen = Grid {
+            width: grid.width,
+            height: grid.height,
+            bytes: vec![false; grid.width * grid.height],
+        };
+        seen[*start] = true;
+
+        let mut count = Grid {
+            width: grid.width,
+            height: grid.height,
+            bytes: vec![0usize; grid.width * grid.height],
+        };
+        count[*start] = 1;
+
+        while let Some(pos) = queue.pop_front() {
+            let base_val = count[pos];
+            let down_pos = pos + DOWN;
+            if !grid.contains(down_pos) {
+                continue;
+            }
+
+            // NOTE: we cannot use a while loop like in part1,
+            // because we need to propagate counts correctly
+            // we go 'level by level' in a BFS style
+            if grid[down_pos] == b'^' {
+                let p1 = down_pos + LEFT;
+                let p2 = down_pos + RIGHT;
+                count[p1] += base_val;
+                count[p2] += base_val;
+                if !seen[p1] {
+                    seen[p1] = true;
+                    queue.push_back(p1);
+                }
+                if !seen[p2] {
+                    seen[p2] = true;
+                    queue.push_back(p2);
+                }
+            } else {
+                count[down_pos] += base_val;
+                if !seen[down_pos] {
+                    queue.push_back(down_pos);
+                }
+                seen[down_pos] = true;
+            }
+        }
+        let mut res = 0;
+        for x in 0..grid.width {
+            let p = Point::new(x as i64, (grid.height - 1) as i64);
+            res += count[p];
+        }
+        res
+    }
+
+    // splitters are never next to each other like in the puzzle input, and stay away from the sides
+    // unless `sides`: the references can't handle either
+    fn random_manifold(rng: &mut Rng, width: usize, height: usize, sides: bool) -> String {
+        let mut res = String::new();
+        let start = 1 + rng.below(width as u64 - 2) as usize;
+        for y in 0..height {
+            for x in 0..width {
+                let c = if y == 0 && x == start {
+                    'S'
+                } else if y % 2 == 0
+                    && (sides || (x > 0 && x + 1 < width))
+                    && !res.ends_with('^')
+                    && rng.below(3) == 0
+                {
+                    '^'
+                } else {
+                    '.'
+                };
+                res.push(c);
+            }
+            res.push('\n');
+        }
+        res
+    }
+
+    #[test]
+    fn row_dp_matches_bfs() {
+        assert_eq!(part2_bfs(&parse(EXAMPLE)), 40);
+        let mut rng = Rng::new(7);
+        for _ in 0..50 {
+            let width = 3 + rng.below(20) as usize;
+            let height = 2 + rng.below(30) as usize;
+            let manifold = random_manifold(&mut rng, width, height, false);
+            let expected = part2_bfs(&parse(&manifold));
+            assert_eq!(part2(&parse(&manifold)), Ok(expected), "{manifold}");
+            assert_eq!(
+                count_bottom_exits::<usize>(manifold.as_bytes()).unwrap(),
+                expected
+            );
+            assert_eq!(part1(&parse(&manifold)), part1_dfs(&parse(&manifold)));
+        }
+        // the row DP loses the beams leaving through the sides, part2 ends their timeline
+        for _ in 0..50 {
+            let width = 3 + rng.below(10) as usize;
+            let height = 2 + rng.below(30) as usize;
+            let manifold = random_manifold(&mut rng, width, height, true);
+            let input = parse(&manifold);
+            let exits = exit_histogram::<usize>(&input.0).unwrap();
+            assert_eq!(
+                count_bottom_exits::<usize>(manifold.as_bytes()).unwrap(),
+                exits.columns.iter().sum::<usize>(),
+                "{manifold}"
+            );
+            assert_eq!(part2(&input), exits.total(), "{manifold}");
+        }
+    }
+
+    #[test]
+    fn splitter_on_a_side_column() {
+        for (manifold, left, right) in [("S.\n^.\n..", 1, 0), (".S\n.^\n..", 0, 1), ("S\n^", 1, 1)]
+        {
+            let input = parse(manifold);
+            let exits = exit_histogram::<usize>(&input.0).unwrap();
+            assert_eq!((exits.left, exits.right), (left, right), "{manifold}");
+            let bottom: usize = exits.columns.iter().sum();
+            assert_eq!(bottom + left + right, 2);
+            assert_eq!(part2(&input), Ok(2));
+            assert_eq!(part2(&input), exits.total());
+            assert_eq!(
+                count_bottom_exits::<usize>(manifold.as_bytes()).unwrap(),
+                bottom,
+                "{manifold}"
+            );
+        }
+    }
+
+    // the original DFS, as a reference
+    fn part1_dfs(input: &Input) -> usize {
+        let (grid, start) = input;
+        let mut queue = vec![*start];
+        let mut res = 0;
+        let mut seen = Grid {
+            width: grid.width,
+            height: grid.height,
+            bytes: vec![false; grid.width * grid.height],
+        };
+        seen[*start] = true;
+
+        while let Some(pos) = queue.pop() {
+            let mut down_pos = pos + DOWN;
+
+            while grid.contains(down_pos) {
+                if grid[down_pos] == b'^' {
+                    if !seen[down_pos] {
+                        res += 1;
+                    }
+                    seen[down_pos] = true;
+
+                    let p1 = down_pos + LEFT;
+                    let p2 = down_pos + RIGHT;
+                    if !seen[p1] && grid.contains(p1) {
+                        seen[p1] = true;
+                        queue.push(p1);
+                    }
+                    if !seen[p2] && grid.contains(p2) {
+                        seen[p2] = true;
+                        queue.push(p2);
+                    }
+                    break;
+                }
+                seen[down_pos] = true;
+                down_pos += DOWN;
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn splitter_dag() {
+        let dag = SplitterDag::new(&parse(EXAMPLE).0).unwrap();
+        assert_eq!(dag.nodes.len(), 22);
+        assert_eq!(dag.reachable(), 21);
+        assert_eq!(dag.paths::<usize>(), Ok(40));
+        assert_eq!(dag.roots, [Target::Splitter(0)]);
+
+        // the splitter above the source is never hit
+        let dag = SplitterDag::new(&parse(".S^\n.^.\n...\n^..").0).unwrap();
+        let exit = |x, y| Target::Exit(Point::new(x, y));
+        assert_eq!(
+            dag.nodes,
+            [
+                SplitterNode {
+                    pos: Point::new(2, 0),
+                    left: Target::Splitter(1),
+                    right: exit(3, 0),
+                    never_hit: true,
+                },
+                SplitterNode {
+                    pos: Point::new(1, 1),
+                    left: Target::Splitter(2),
+                    right: exit(2, 4),
+                    never_hit: false,
+                },
+                SplitterNode {
+                    pos: Point::new(0, 3),
+                    left: exit(-1, 3),
+                    right: exit(1, 4),
+                    never_hit: false,
+                },
+            ]
+        );
+        assert_eq!(dag.reachable(), 2);
+        assert_eq!(dag.paths::<usize>(), Ok(3));
+        assert_eq!(
+            dag.to_json(),
+            r#"{"nodes":[{"x":2,"y":0,"left":1,"right":{"exit":[3,0]},"never_hit":true},{"x":1,"y":1,"left":2,"right":{"exit":[2,4]},"never_hit":false},{"x":0,"y":3,"left":{"exit":[-1,3]},"right":{"exit":[1,4]},"never_hit":false}],"roots":[1]}"#
+        );
+        assert_eq!(
+            dag.to_dot(),
+            "digraph splitters {
+  n0 [label=\"2,0\", style=dashed];
+  n1 [label=\"1,1\"];
+  n2 [label=\"0,3\"];
+  exit [shape=doublecircle];
+  s0 [shape=point];
+  s0 -> n1;
+  n0 -> n1 [label=L];
+  n0 -> exit [label=R];
+  n1 -> n2 [label=L];
+  n1 -> exit [label=R];
+  n2 -> exit [label=L];
+  n2 -> exit [label=R];
+}
+"
+        );
+
+        // the same counts as the engine
+        let mut rng = Rng::new(46);
+        for sides in [false, true] {
+            for _ in 0..50 {
+                let width = 3 + rng.below(15) as usize;
+                let height = 2 + rng.below(30) as usize;
+                let input = parse(&random_manifold(&mut rng, width, height, sides));
+                let dag = SplitterDag::new(&input.0).unwrap();
+                let engine = BeamEngine::new(&input.0);
+                assert_eq!(dag.reachable(), engine.splitters_hit());
+                assert_eq!(dag.paths::<u64>(), engine.timelines());
+            }
+        }
+        assert_eq!(SplitterDag::new(&parse("S\n/").0), None);
+    }
+
+    #[test]
+    fn several_sources() {
+        let input = parse(".S..S.\n......\n.^..^.\n......\n.....^");
+        assert_eq!(part1(&input), 3);
+        assert_eq!(part2(&input), Ok(5));
+    }
+
+    #[test]
+    fn mirrors_and_splitters() {
+        // down, right at the mirror, then split up and down at the `|`
+        let input = parse(".S...\n.....\n.\\.|.\n.....");
+        assert_eq!(part2(&input), Ok(2));
+        // coming along a splitter goes through it
+        let input = parse("S\n|\n-\n.");
+        assert_eq!(part2(&input), Ok(2));
+        let input = parse("S.\n..\n/^\n..");
+        // the `/` sends the beam left, out of the grid
+        assert_eq!(part1(&input), 0);
+        assert_eq!(part2(&input), Ok(1));
+        // `^` works whatever the direction
+        let input = parse(".S...\n.\\.^.\n.....");
+        assert_eq!(part1(&input), 1);
+        assert_eq!(part2(&input), Ok(2));
+        let engine_grid = &input.0;
+        let engine = BeamEngine::new(engine_grid);
+        let split = engine.step(Beam {
+            pos: Point::new(2, 1),
+            dir: RIGHT,
+        });
+        assert_eq!(
+            split,
+            [
+                Some(Beam {
+                    pos: Point::new(3, 0),
+                    dir: RIGHT
+                }),
+                Some(Beam {
+                    pos: Point::new(3, 2),
+                    dir: RIGHT
+                })
+            ]
+        );
+    }
+
+    // every beam hits a splitter at each level, doubling the timelines
+    fn galton_board(levels: usize) -> String {
+        let width = 2 * levels + 3;
+        let center = levels + 1;
+        let mut rows = vec![format!("{}S{}", ".".repeat(center), ".".repeat(center))];
+        for level in 0..levels {
+            let row: String = (0..width)
+                .map(|x| {
+                    let offset = x as i64 - center as i64;
+                    if offset.unsigned_abs() as usize <= level && (offset + level as i64) % 2 == 0 {
+                        '^'
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            rows.push(".".repeat(width));
+            rows.push(row);
+        }
+        rows.join("\n")
+    }
+
+    #[test]
+    fn big_counts() {
+        let board = galton_board(70);
+        let input = parse(&board);
+        let engine = BeamEngine::new(&input.0);
+        assert_eq!(engine.timelines::<u128>(), Ok(1 << 70));
+        assert_eq!(engine.timelines::<u64>(), Err(TimelineError::Overflow));
+        assert_eq!(part1(&input), 70 * 71 / 2);
+
+        const P: u64 = 1_000_000_007;
+        let expected = (0..70).fold(1, |acc, _| acc * 2 % P);
+        assert_eq!(engine.timelines::<ModPrime<P>>(), Ok(ModPrime(expected)));
+
+        assert_eq!(
+            count_bottom_exits::<u128>(board.as_bytes()).unwrap(),
+            1 << 70
+        );
+        assert_eq!(
+            count_bottom_exits::<ModPrime<P>>(board.as_bytes()).unwrap(),
+            ModPrime(expected)
+        );
+        let err = count_bottom_exits::<u64>(board.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // just fits
+        let input = parse(&galton_board(63));
+        let engine = BeamEngine::new(&input.0);
+        assert_eq!(engine.timelines::<u64>(), Ok(1 << 63));
+    }
+
+    fn binomial(n: u128, k: u128) -> u128 {
+        (0..k).fold(1, |acc, i| acc * (n - i) / (i + 1))
+    }
+
+    #[test]
+    fn exit_distributions() {
+        let input = parse(EXAMPLE);
+        let exits = exit_histogram::<usize>(&input.0).unwrap();
+        assert_eq!(
+            exits.columns,
+            vec![1, 0, 2, 0, 10, 0, 11, 0, 11, 0, 2, 1, 1, 0, 1]
+        );
+        assert_eq!(exits.total(), Ok(40));
+
+        let half = Ratio::new(1, 2);
+        let probabilities = exit_probabilities(&input.0, half).unwrap();
+        assert_eq!(probabilities.total(), Ok(Ratio::ONE));
+
+        // the galton board gives the binomial distribution
+        let n = 10;
+        let input = parse(&galton_board(n));
+        let exits = exit_histogram::<u64>(&input.0).unwrap();
+        let p = Ratio::new(1, 3);
+        let probabilities = exit_probabilities(&input.0, p).unwrap();
+        for k in 0..=n {
+            // k beams went right
+            let column = 1 + 2 * k;
+            let c = binomial(n as u128, k as u128);
+            assert_eq!(exits.columns[column], c as u64);
+            let expected = Ratio::new(c * 2u128.pow(k as u32), 3u128.pow(n as u32));
+            assert_eq!(probabilities.columns[column], expected);
+        }
+        assert_eq!(probabilities.total(), Ok(Ratio::ONE));
+
+        // denominators stay at 2^70
+        let input = parse(&galton_board(70));
+        let probabilities = exit_probabilities(&input.0, half).unwrap();
+        assert_eq!(probabilities.total(), Ok(Ratio::ONE));
+        assert_eq!(probabilities.columns[1], Ratio::new(1, 1 << 70));
+
+        // the exits add up to part2 whatever the grid
+        for manifold in [
+            ".S..S.\n......\n.^..^.\n......\n.....^",
+            ".S...\n.....\n.\\.|.\n.....",
+            "S.\n..\n/^\n..",
+            "S\n^",
+        ] {
+            let input = parse(manifold);
+            let exits = exit_histogram::<usize>(&input.0).unwrap();
+            assert_eq!(exits.total(), part2(&input), "{manifold}");
+            let sources = BeamEngine::new(&input.0).sources().count() as u128;
+            let probabilities = exit_probabilities(&input.0, p).unwrap();
+            assert_eq!(probabilities.total(), Ok(Ratio::new(sources, 1)));
+        }
+        // up at the `|`, out through the top
+        let input = parse(".S...\n.....\n.\\.|.\n.....");
+        let exits = exit_histogram::<usize>(&input.0).unwrap();
+        assert_eq!((exits.top, exits.columns[3]), (1, 1));
+        let probabilities = exit_probabilities(&input.0, p).unwrap();
+        assert_eq!(probabilities.top, p);
+        assert_eq!(probabilities.columns[3], Ratio::new(2, 3));
+        let exits = exit_histogram::<usize>(&parse("S\n^").0).unwrap();
+        assert_eq!((exits.left, exits.right), (1, 1));
+
+        let too_likely = Ratio::new(3, 2);
+        assert_eq!(
+            exit_probabilities(&input.0, too_likely),
+            Err(TimelineError::InvalidProbability { p: too_likely })
+        );
+        let looping = parse(".S...\n.-..\\\n.....\n.\\../");
+        assert!(matches!(
+            exit_histogram::<usize>(&looping.0),
+            Err(TimelineError::Loop { .. })
+        ));
+    }
+
+    #[test]
+    fn enumerate_timelines() {
+        let input = parse(EXAMPLE);
+        let engine = BeamEngine::new(&input.0);
+        let all: Vec<Timeline> = engine
+            .enumerate_timelines(usize::MAX, None)
+            .unwrap()
+            .collect();
+        assert_eq!(all.len(), 40);
+        // left first, so all the way left comes first
+        assert_eq!(all[0].to_string(), "LLLLLLL -> 0,16");
+        assert!(all.windows(2).all(|w| w[0].choices <= w[1].choices));
+        let mut distinct = all.clone();
+        distinct.dedup();
+        assert_eq!(distinct.len(), 40);
+
+        // the exits match the histogram
+        let mut columns = vec![0; 15];
+        for t in &all {
+            columns[t.exit.pos.x as usize] += 1;
+        }
+        assert_eq!(exit_histogram::<usize>(&input.0).unwrap().columns, columns);
+
+        assert_eq!(engine.enumerate_timelines(5, None).unwrap().count(), 5);
+        let shuffled: Vec<Timeline> = engine.enumerate_timelines(40, Some(3)).unwrap().collect();
+        let again: Vec<Timeline> = engine.enumerate_timelines(40, Some(3)).unwrap().collect();
+        assert_eq!(shuffled, again);
+        assert_ne!(shuffled, all);
+        let mut sorted = shuffled.clone();
+        sorted.sort_by(|a, b| a.choices.cmp(&b.choices));
+        assert_eq!(sorted, all);
+
+        // lazy, even with way too many timelines to list
+        let board = parse(&galton_board(70));
+        let engine = BeamEngine::new(&board.0);
+        assert_eq!(engine.enumerate_timelines(3, None).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn sample_timelines() {
+        let input = parse(EXAMPLE);
+        let engine = BeamEngine::new(&input.0);
+        let all: Vec<Timeline> = engine
+            .enumerate_timelines(usize::MAX, None)
+            .unwrap()
+            .collect();
+        let samples: Vec<Timeline> = engine.sample_timelines(1).unwrap().take(4000).collect();
+        assert_eq!(
+            samples,
+            engine
+                .sample_timelines(1)
+                .unwrap()
+                .take(4000)
+                .collect::<Vec<_>>()
+        );
+        // every timeline is as likely, about 100 times each
+        for t in &all {
+            let n = samples.iter().filter(|s| *s == t).count();
+            assert!((50..200).contains(&n), "{t}: {n}");
+        }
+
+        let board = parse(&galton_board(70));
+        let engine = BeamEngine::new(&board.0);
+        let t = engine.sample_timelines(1).unwrap().next().unwrap();
+        assert_eq!(t.choices.len(), 70);
+    }
+
+    #[test]
+    fn loops() {
+        // the `-` sends a beam right, which comes back to it from below through the mirrors
+        let input = parse(".S...\n.-..\\\n.....\n.\\../");
+        assert_eq!(part1(&input), 0);
+        assert!(matches!(part2(&input), Err(TimelineError::Loop { .. })));
+        let engine = BeamEngine::new(&input.0);
+        assert!(engine.enumerate_timelines(10, None).is_err());
+        assert!(engine.sample_timelines(0).is_err());
     }
 }
@@ -1,161 +1,717 @@
 use aoc_runner_derive::{aoc, aoc_generator};
+use gxhash::{HashMap, HashMapExt};
+
+use crate::disjoint_set::DisjointSet;
 
 use std::cmp::Reverse;
-use std::collections::BinaryHeap;
+use std::collections::{BTreeSet, BinaryHeap};
+use std::fmt;
+use std::ops::Range;
 
+/// A junction box in `D` dimensions.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
-pub struct Point {
-    pub x: i32,
-    pub y: i32,
-    pub z: i32,
+pub struct Point<const D: usize = 3> {
+    pub coords: [i32; D],
 }
 
-impl Point {
-    pub fn new(x: i32, y: i32, z: i32) -> Self {
-        Point { x, y, z }
+impl<const D: usize> Point<D> {
+    pub fn new(coords: [i32; D]) -> Self {
+        Point { coords }
     }
 
-    pub fn euclidean_distance(&self, other: &Point) -> f64 {
-        let dx = (self.x - other.x) as f64;
-        let dy = (self.y - other.y) as f64;
-        let dz = (self.z - other.z) as f64;
-        dx * dx + dy * dy + dz * dz
+    fn deltas(&self, other: &Point<D>) -> impl Iterator<Item = i64> {
+        (0..D).map(|a| self.coords[a] as i64 - other.coords[a] as i64)
     }
-}
 
-// wrapper to implement Ord for f64
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
-struct F64Ord(pub f64);
+    /// Exact squared euclidean distance, as `i32` coordinates can be 2^32 apart
+    /// the squares need an `i128`.
+    pub fn squared_distance(&self, other: &Point<D>) -> i128 {
+        self.deltas(other).map(|d| d as i128 * d as i128).sum()
+    }
 
-impl Eq for F64Ord {}
+    pub fn manhattan_distance(&self, other: &Point<D>) -> i64 {
+        self.deltas(other).map(|d| d.abs()).sum()
+    }
 
-impl Ord for F64Ord {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+    pub fn chebyshev_distance(&self, other: &Point<D>) -> i64 {
+        self.deltas(other).map(|d| d.abs()).max().unwrap_or(0)
     }
 }
 
-pub struct DisjointSet {
-    pub parent: Vec<usize>,
-    pub rank: Vec<usize>,
-    pub components: usize,
+/// How the distance between two junction boxes is measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Metric {
+    /// Squared, which keeps it exact without changing the order of the pairs.
+    #[default]
+    Euclidean,
+    Manhattan,
+    Chebyshev,
 }
 
-impl DisjointSet {
-    pub fn new(size: usize) -> Self {
-        let parent = (0..size).collect();
-        let rank = vec![0; size];
-        Self {
-            parent,
-            rank,
-            components: size,
+impl Metric {
+    pub fn distance<const D: usize>(self, a: &Point<D>, b: &Point<D>) -> i128 {
+        match self {
+            Metric::Euclidean => a.squared_distance(b),
+            Metric::Manhattan => a.manhattan_distance(b).into(),
+            Metric::Chebyshev => a.chebyshev_distance(b).into(),
         }
     }
 
-    pub fn find(&mut self, mut x: usize) -> usize {
-        while self.parent[x] != x {
-            self.parent[x] = self.parent[self.parent[x]];
-            x = self.parent[x];
+    // smallest distance between two points that are `gap` apart along one of the axes
+    fn lower_bound(self, gap: i64) -> i128 {
+        let gap = i128::from(gap);
+        match self {
+            Metric::Euclidean => gap * gap,
+            Metric::Manhattan | Metric::Chebyshev => gap,
         }
-        x
     }
+}
 
-    pub fn union(&mut self, x: usize, y: usize) {
-        let root_x = self.find(x);
-        let root_y = self.find(y);
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// A line does not have the same number of coordinates as the first one.
+    MixedDimensions {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// The lines agree with each other, but not with the requested dimension.
+    WrongDimension {
+        expected: usize,
+        found: usize,
+    },
+    InvalidCoordinate {
+        line: usize,
+    },
+}
 
-        if root_x != root_y {
-            if self.rank[root_x] > self.rank[root_y] {
-                self.parent[root_y] = root_x;
-            } else if self.rank[root_x] < self.rank[root_y] {
-                self.parent[root_x] = root_y;
-            } else {
-                self.parent[root_y] = root_x;
-                self.rank[root_x] += 1;
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::MixedDimensions {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {line}: expected {expected} coordinates like the first line, found {found}"
+            ),
+            ParseError::WrongDimension { expected, found } => {
+                write!(f, "expected {expected} coordinates per line, found {found}")
             }
-            self.components -= 1;
+            ParseError::InvalidCoordinate { line } => write!(f, "line {line}: invalid coordinate"),
         }
     }
 }
 
+impl std::error::Error for ParseError {}
+
+/// Number of coordinates per line, checking that every line agrees.
+pub fn dimension(input: &str) -> Result<usize, ParseError> {
+    let mut expected = None;
+    for (i, l) in input.lines().enumerate() {
+        let found = l.split(',').count();
+        match expected {
+            None => expected = Some(found),
+            Some(expected) if expected != found => {
+                return Err(ParseError::MixedDimensions {
+                    line: i + 1,
+                    expected,
+                    found,
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(expected.unwrap_or(0))
+}
+
+pub fn parse_points<const D: usize>(input: &str) -> Result<Vec<Point<D>>, ParseError> {
+    let found = dimension(input)?;
+    if found != D && !input.is_empty() {
+        return Err(ParseError::WrongDimension { expected: D, found });
+    }
+    let mut res = Vec::new();
+    for (i, l) in input.lines().enumerate() {
+        let mut coords = [0; D];
+        for (c, n) in coords.iter_mut().zip(l.split(',')) {
+            *c = n
+                .trim()
+                .parse()
+                .map_err(|_| ParseError::InvalidCoordinate { line: i + 1 })?;
+        }
+        res.push(Point::new(coords));
+    }
+    Ok(res)
+}
+
 type Input = Vec<Point>;
 
 #[aoc_generator(day8)]
 pub fn parse(input: &str) -> Input {
-    let mut res = Vec::new();
-    for l in input.lines() {
-        let xyz: Vec<i32> = l.split(',').map(|n| n.parse::<i32>().unwrap()).collect();
-        res.push(Point::new(xyz[0], xyz[1], xyz[2]));
+    parse_points(input).unwrap()
+}
+
+type Cell<const D: usize> = [i64; D];
+
+/// Uniform grid bucketing the points, so that neighbours can be found
+/// without looking at every pair.
+pub struct SpatialGrid<const D: usize> {
+    cell_size: i64,
+    cells: HashMap<Cell<D>, Vec<usize>>,
+    // bounding box of the occupied cells
+    min_cell: Cell<D>,
+    max_cell: Cell<D>,
+}
+
+impl<const D: usize> SpatialGrid<D> {
+    pub fn new(points: &[Point<D>]) -> Self {
+        // aim for about one point per cell
+        let mut volume = 1.0;
+        for a in 0..D {
+            let min = points.iter().map(|p| p.coords[a]).min().unwrap_or(0) as f64;
+            let max = points.iter().map(|p| p.coords[a]).max().unwrap_or(0) as f64;
+            volume *= max - min + 1.0;
+        }
+        let per_point = volume / points.len().max(1) as f64;
+        let cell_size = per_point.powf(1.0 / D as f64).max(1.0) as i64;
+
+        let mut grid = SpatialGrid::with_cell_size(cell_size);
+        grid.cells.reserve(points.len());
+        for (i, p) in points.iter().enumerate() {
+            grid.insert(i, p);
+        }
+        grid
+    }
+
+    pub fn with_cell_size(cell_size: i64) -> Self {
+        SpatialGrid {
+            cell_size: cell_size.max(1),
+            cells: HashMap::new(),
+            min_cell: [i64::MAX; D],
+            max_cell: [i64::MIN; D],
+        }
+    }
+
+    fn cell_of(&self, p: &Point<D>) -> Cell<D> {
+        p.coords.map(|v| (v as i64).div_euclid(self.cell_size))
+    }
+
+    pub fn insert(&mut self, idx: usize, p: &Point<D>) {
+        let cell = self.cell_of(p);
+        for (a, c) in cell.into_iter().enumerate() {
+            self.min_cell[a] = self.min_cell[a].min(c);
+            self.max_cell[a] = self.max_cell[a].max(c);
+        }
+        self.cells.entry(cell).or_default().push(idx);
+    }
+
+    // calls `f` on every point in the cells at exactly `radius` cells from `center`
+    fn for_each_in_shell(&self, center: Cell<D>, radius: i64, mut f: impl FnMut(usize)) {
+        let mut cell = center;
+        self.visit_shell(center, radius, 0, false, &mut cell, &mut f);
+    }
+
+    // fixes the offset along `axis` and recurses on the next ones,
+    // `on_shell` tells if one of the previous offsets already reaches the shell
+    fn visit_shell(
+        &self,
+        center: Cell<D>,
+        r: i64,
+        axis: usize,
+        on_shell: bool,
+        cell: &mut Cell<D>,
+        f: &mut impl FnMut(usize),
+    ) {
+        if axis == D {
+            if let Some(idxs) = self.cells.get(cell) {
+                idxs.iter().for_each(|&j| f(j));
+            }
+            return;
+        }
+        // no need to look outside of the occupied cells
+        let lo = (-r).max(self.min_cell[axis] - center[axis]);
+        let hi = r.min(self.max_cell[axis] - center[axis]);
+        let mut visit = |d: i64| {
+            cell[axis] = center[axis] + d;
+            self.visit_shell(center, r, axis + 1, on_shell || d.abs() == r, cell, f);
+        };
+        let reaches =
+            |a: usize| self.min_cell[a] - center[a] <= -r || self.max_cell[a] - center[a] >= r;
+        if on_shell || (axis + 1..D).any(reaches) {
+            (lo..=hi).for_each(visit);
+        } else if r == 0 {
+            visit(0);
+        } else {
+            // the next axes can't reach the shell within the occupied cells, so this one
+            // has to: only its two faces are left
+            [-r, r]
+                .into_iter()
+                .filter(|d| (lo..=hi).contains(d))
+                .for_each(visit);
+        }
+    }
+
+    // number of cells, occupied or not, in the shells up to `radius` that get visited
+    fn cells_within(&self, center: Cell<D>, radius: i64) -> usize {
+        if self.cells.is_empty() {
+            return 0;
+        }
+        (0..D)
+            .map(|a| {
+                let lo = (-radius).max(self.min_cell[a] - center[a]);
+                let hi = radius.min(self.max_cell[a] - center[a]);
+                (hi - lo + 1).max(0) as usize
+            })
+            .fold(1, usize::saturating_mul)
+    }
+
+    // the shell radius after which every occupied cell has been visited
+    fn max_radius(&self, center: Cell<D>) -> i64 {
+        if self.cells.is_empty() {
+            return -1;
+        }
+        (0..D)
+            .map(|a| (center[a] - self.min_cell[a]).max(self.max_cell[a] - center[a]))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Yields the neighbours of a point in increasing `(distance, j)` order,
+/// by visiting the grid cells shell by shell.
+struct NeighbourCursor {
+    // last shell visited, -1 if none yet
+    radius: i64,
+    candidates: BinaryHeap<Reverse<(i128, usize)>>,
+}
+
+impl NeighbourCursor {
+    fn new() -> Self {
+        NeighbourCursor {
+            radius: -1,
+            candidates: BinaryHeap::new(),
+        }
+    }
+
+    // only neighbours with an index in `accept` are considered,
+    // it must be the same range on every call
+    fn next<const D: usize>(
+        &mut self,
+        i: usize,
+        accept: Range<usize>,
+        points: &[Point<D>],
+        grid: &SpatialGrid<D>,
+        metric: Metric,
+    ) -> Option<(i128, usize)> {
+        self.next_within(i, accept, points, grid, metric, usize::MAX)
+            .unwrap_or(None)
+    }
+
+    // like `next`, but gives up instead of visiting more than `max_cells` cells in total
+    fn next_within<const D: usize>(
+        &mut self,
+        i: usize,
+        accept: Range<usize>,
+        points: &[Point<D>],
+        grid: &SpatialGrid<D>,
+        metric: Metric,
+        max_cells: usize,
+    ) -> Result<Option<(i128, usize)>, TooFar> {
+        let center = grid.cell_of(&points[i]);
+        let max_radius = grid.max_radius(center);
+        loop {
+            let exhausted = self.radius >= max_radius;
+            if let Some(&Reverse((dist, j))) = self.candidates.peek() {
+                // any point outside the visited shells is at least this far along one axis
+                let gap = self.radius * grid.cell_size + 1;
+                if exhausted || dist < metric.lower_bound(gap) {
+                    self.candidates.pop();
+                    return Ok(Some((dist, j)));
+                }
+            } else if exhausted {
+                return Ok(None);
+            }
+
+            if grid.cells_within(center, self.radius + 1) > max_cells {
+                return Err(TooFar);
+            }
+            self.radius += 1;
+            grid.for_each_in_shell(center, self.radius, |j| {
+                if accept.contains(&j) {
+                    let dist = metric.distance(&points[i], &points[j]);
+                    self.candidates.push(Reverse((dist, j)));
+                }
+            });
+        }
     }
-    res
 }
 
-fn build_min_pairs(input: &Input) -> BinaryHeap<Reverse<(F64Ord, usize, usize)>> {
-    let mut heap: BinaryHeap<Reverse<(F64Ord, usize, usize)>> = BinaryHeap::new();
-    for i in 0..input.len() {
-        for j in (i + 1)..input.len() {
-            let p1 = input[i];
-            let p2 = input[j];
-            let dist = p1.euclidean_distance(&p2);
-            heap.push(Reverse((F64Ord(dist), i, j)));
+// the next neighbour is further than the cells a cursor may visit
+struct TooFar;
+
+/// Every pair of points, lazily yielded as `(distance, i, j)` with `i < j`.
+///
+/// Pairs come out by increasing distance, and pairs at the same distance
+/// by increasing `i` then `j` (their lines in the input), so the order never
+/// depends on how the pairs were found.
+pub struct PairStream<'a, const D: usize> {
+    points: &'a [Point<D>],
+    metric: Metric,
+    grid: SpatialGrid<D>,
+    cursors: Vec<NeighbourCursor>,
+    heap: BinaryHeap<Reverse<(i128, usize, usize)>>,
+}
+
+impl<'a, const D: usize> PairStream<'a, D> {
+    pub fn new(points: &'a [Point<D>], metric: Metric) -> Self {
+        let grid = SpatialGrid::new(points);
+        let mut cursors: Vec<NeighbourCursor> =
+            (0..points.len()).map(|_| NeighbourCursor::new()).collect();
+        // each point only contributes its next closest neighbour to the heap at any time
+        let mut heap = BinaryHeap::with_capacity(points.len());
+        for (i, cursor) in cursors.iter_mut().enumerate() {
+            if let Some((dist, j)) = cursor.next(i, i + 1..points.len(), points, &grid, metric) {
+                heap.push(Reverse((dist, i, j)));
+            }
+        }
+        PairStream {
+            points,
+            metric,
+            grid,
+            cursors,
+            heap,
         }
     }
-    heap
 }
 
-#[aoc(day8, part1)]
-pub fn part1(input: &Input) -> usize {
-    part1_testable(input, 1000)
+impl<const D: usize> Iterator for PairStream<'_, D> {
+    type Item = (i128, usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((dist, i, j)) = self.heap.pop()?;
+        let accept = i + 1..self.points.len();
+        let next = self.cursors[i].next(i, accept, self.points, &self.grid, self.metric);
+        if let Some((next_dist, next_j)) = next {
+            self.heap.push(Reverse((next_dist, i, next_j)));
+        }
+        Some((dist, i, j))
+    }
 }
 
-pub fn part1_testable(input: &Input, n: usize) -> usize {
-    let mut heap = build_min_pairs(input);
-    let mut diset = DisjointSet::new(input.len());
-    for _ in 0..n {
-        let entry = heap.pop().unwrap();
-        let (_, p1, p2) = entry.0;
-        // already connected
-        if diset.find(p1) != diset.find(p2) {
-            diset.union(p1, p2);
+/// One step of the single-linkage clustering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Merge {
+    pub distance: i128,
+    /// The pair of junction boxes whose connection caused the merge.
+    pub pair: (usize, usize),
+    /// Ids below `len` are single boxes, the `k`th merge creates cluster `len + k`.
+    pub clusters: (usize, usize),
+    /// Size of the new cluster.
+    pub size: usize,
+    /// How many pairs had been connected at this point, this one included.
+    pub edges: usize,
+}
+
+/// Full merge history of Kruskal's algorithm over the pairs of junction boxes,
+/// from which any cut of the clustering can be replayed cheaply.
+#[derive(Debug, Clone)]
+pub struct Dendrogram {
+    pub len: usize,
+    pub merges: Vec<Merge>,
+}
+
+impl Dendrogram {
+    pub fn build<const D: usize>(points: &[Point<D>], metric: Metric) -> Self {
+        Self::build_partial(points, metric, usize::MAX)
+    }
+
+    /// Same as `build`, but stops after connecting `max_edges` pairs.
+    pub fn build_partial<const D: usize>(
+        points: &[Point<D>],
+        metric: Metric,
+        max_edges: usize,
+    ) -> Self {
+        let len = points.len();
+        let mut diset = DisjointSet::new(len);
+        // cluster id of each root
+        let mut cluster: Vec<usize> = (0..len).collect();
+        let mut merges = Vec::with_capacity(len.saturating_sub(1));
+
+        let mut pairs = PairStream::new(points, metric);
+        let mut edges = 0;
+        while diset.components() > 1 && edges < max_edges {
+            let Some((distance, p1, p2)) = pairs.next() else {
+                break;
+            };
+            edges += 1;
+            let (c1, c2) = (cluster[diset.find(p1)], cluster[diset.find(p2)]);
+            if diset.union(p1, p2) {
+                cluster[diset.find(p1)] = len + merges.len();
+                merges.push(Merge {
+                    distance,
+                    pair: (p1, p2),
+                    clusters: (c1, c2),
+                    size: diset.size(p1),
+                    edges,
+                });
+            }
+        }
+        Dendrogram { len, merges }
+    }
+
+    /// Clustering after applying the first `merges` merges.
+    pub fn replay(&self, merges: usize) -> DisjointSet {
+        let mut diset = DisjointSet::new(self.len);
+        for m in &self.merges[..merges] {
+            diset.union(m.pair.0, m.pair.1);
         }
+        diset
     }
 
-    let n = diset.parent.len();
-    let mut counts = vec![0usize; n];
-    for i in 0..n {
-        let root = diset.find(i);
-        counts[root] += 1;
+    /// Clustering once the `edges` closest pairs have been connected.
+    pub fn after_edges(&self, edges: usize) -> DisjointSet {
+        self.replay(self.merges.partition_point(|m| m.edges <= edges))
     }
-    counts.sort_unstable_by(|a, b| b.cmp(a));
-    let mut res = 1;
-    for size in counts.into_iter().take(3) {
-        res *= size;
+
+    /// Clustering when every pair at most `distance` apart is connected.
+    pub fn at_distance(&self, distance: i128) -> DisjointSet {
+        self.replay(self.merges.partition_point(|m| m.distance <= distance))
+    }
+
+    /// Clustering with `k` clusters, or as few as possible if `k` is too small.
+    pub fn with_clusters(&self, k: usize) -> DisjointSet {
+        self.replay(self.len.saturating_sub(k).min(self.merges.len()))
+    }
+
+    /// The merge that connected everything, if there is more than one junction box.
+    pub fn last_merge(&self) -> Option<&Merge> {
+        self.merges
+            .last()
+            .filter(|_| self.merges.len() + 1 == self.len)
     }
-    res
 }
 
-#[aoc(day8, part2)]
-pub fn part2(input: &Input) -> usize {
-    let mut heap = build_min_pairs(input);
-    let mut diset = DisjointSet::new(input.len());
-    let mut last_pair = None;
-    while diset.components > 1 {
-        let entry = heap.pop().unwrap();
-        let (_, p1, p2) = entry.0;
-
-        // already connected
-        if diset.find(p1) != diset.find(p2) {
-            diset.union(p1, p2);
-            last_pair = Some((p1, p2));
+type Edge = (i128, usize, usize);
+
+/// Minimum spanning tree kept up to date while junction boxes are added one at a time.
+///
+/// A new box can only use its own edges that are no longer than its closest neighbour
+/// or the longest edge of the current tree, whichever is longer (any longer one closes
+/// a cycle in which it is the longest), so only those are looked up in the grid.
+/// When that would visit more cells than there are boxes, like next to an outlier,
+/// every box is looked at instead. Each of these edges then replaces the longest edge
+/// on the tree path between its ends, if it is shorter.
+pub struct OnlineMst<const D: usize> {
+    points: Vec<Point<D>>,
+    metric: Metric,
+    grid: SpatialGrid<D>,
+    // (distance, i, j) with i < j, ordered like the pairs of a `PairStream`
+    edges: BTreeSet<Edge>,
+    // the forest as parent pointers, with the edge to the parent of every non root
+    parent: Vec<Option<usize>>,
+    up: Vec<Edge>,
+}
+
+impl<const D: usize> OnlineMst<D> {
+    /// `cell_size` should be about the typical distance between neighbouring boxes.
+    pub fn new(metric: Metric, cell_size: i64) -> Self {
+        OnlineMst {
+            points: Vec::new(),
+            metric,
+            grid: SpatialGrid::with_cell_size(cell_size),
+            edges: BTreeSet::new(),
+            parent: Vec::new(),
+            up: Vec::new(),
         }
     }
-    let (p1, p2) = last_pair.unwrap();
-    input[p1].x as usize * input[p2].x as usize
+
+    /// Starts from a batch of boxes, sizing the grid after them.
+    pub fn from_points(points: &[Point<D>], metric: Metric) -> Self {
+        let n = points.len();
+        let mut mst = OnlineMst {
+            points: points.to_vec(),
+            metric,
+            grid: SpatialGrid::new(points),
+            edges: BTreeSet::new(),
+            parent: vec![None; n],
+            up: (0..n).map(|i| (0, i, i)).collect(),
+        };
+        let mut adjacent = vec![Vec::new(); n];
+        for m in &Dendrogram::build(points, metric).merges {
+            let edge = (m.distance, m.pair.0, m.pair.1);
+            adjacent[m.pair.0].push((m.pair.1, edge));
+            adjacent[m.pair.1].push((m.pair.0, edge));
+            mst.edges.insert(edge);
+        }
+        // hang every tree from its first box
+        let mut seen = vec![false; n];
+        for root in 0..n {
+            if seen[root] {
+                continue;
+            }
+            seen[root] = true;
+            let mut stack = vec![root];
+            while let Some(x) = stack.pop() {
+                for &(y, edge) in &adjacent[x] {
+                    if !seen[y] {
+                        seen[y] = true;
+                        mst.parent[y] = Some(x);
+                        mst.up[y] = edge;
+                        stack.push(y);
+                    }
+                }
+            }
+        }
+        mst
+    }
+
+    pub fn points(&self) -> &[Point<D>] {
+        &self.points
+    }
+
+    /// Edges of the tree as `(distance, i, j)`, shortest first.
+    pub fn edges(&self) -> impl Iterator<Item = Edge> + '_ {
+        self.edges.iter().copied()
+    }
+
+    /// The last pair Kruskal's algorithm would connect, the one part2 is about.
+    pub fn connecting_edge(&self) -> Option<(usize, usize)> {
+        self.edges.last().map(|&(_, i, j)| (i, j))
+    }
+
+    // makes `x` the root of its tree by reversing the path to the current root
+    fn reroot(&mut self, x: usize) {
+        let (mut prev, mut prev_edge) = (None, self.up[x]);
+        let mut cur = Some(x);
+        while let Some(c) = cur {
+            let (next, next_edge) = (self.parent[c], self.up[c]);
+            self.parent[c] = prev;
+            self.up[c] = prev_edge;
+            (prev, prev_edge, cur) = (Some(c), next_edge, next);
+        }
+    }
+
+    // connects the trees of `a` and `b`, which must be different
+    fn link(&mut self, a: usize, b: usize, edge: Edge) {
+        self.reroot(a);
+        self.parent[a] = Some(b);
+        self.up[a] = edge;
+        self.edges.insert(edge);
+    }
+
+    // edges (distance, u, v) from the new box `v` that may be part of the tree,
+    // in the same order as the tree edges
+    fn candidates(&self, v: usize) -> Vec<Edge> {
+        let mut cursor = NeighbourCursor::new();
+        let mut too_far = false;
+        let nearest = std::iter::from_fn(|| {
+            // scanning more cells than there are boxes is slower than a linear pass
+            cursor
+                .next_within(v, 0..v, &self.points, &self.grid, self.metric, v)
+                .unwrap_or_else(|TooFar| {
+                    too_far = true;
+                    None
+                })
+        });
+        let candidates = self.bounded(v, nearest);
+        if !too_far {
+            return candidates;
+        }
+        let mut all: Vec<(i128, usize)> = (0..v)
+            .map(|u| (self.metric.distance(&self.points[v], &self.points[u]), u))
+            .collect();
+        all.sort_unstable();
+        self.bounded(v, all.into_iter())
+    }
+
+    // the edges to `v` from its neighbours, given closest first, until they get too long
+    fn bounded(&self, v: usize, nearest: impl Iterator<Item = (i128, usize)>) -> Vec<Edge> {
+        let mut candidates = Vec::new();
+        let mut bound = self.edges.last().copied();
+        for (dist, u) in nearest {
+            let edge = (dist, u, v);
+            if bound.is_some_and(|bound| edge > bound) && !candidates.is_empty() {
+                break;
+            }
+            if candidates.is_empty() {
+                // the closest neighbour is always part of the tree
+                bound = bound.max(Some(edge));
+            }
+            candidates.push(edge);
+        }
+        candidates
+    }
+
+    /// Adds a box and returns its index.
+    pub fn insert(&mut self, p: Point<D>) -> usize {
+        let v = self.points.len();
+        self.points.push(p);
+        self.parent.push(None);
+        self.up.push((0, v, v));
+
+        let candidates = self.candidates(v);
+        self.grid.insert(v, &p);
+
+        let Some((&closest, others)) = candidates.split_first() else {
+            return v;
+        };
+        self.link(v, closest.1, closest);
+        self.reroot(v);
+        for &edge in others {
+            // with `v` as the root, the path from `u` is a climb
+            let mut longest: Option<usize> = None;
+            let mut x = edge.1;
+            while let Some(next) = self.parent[x] {
+                if longest.is_none_or(|l| self.up[x] > self.up[l]) {
+                    longest = Some(x);
+                }
+                x = next;
+            }
+            let Some(longest) = longest.filter(|&l| self.up[l] > edge) else {
+                continue;
+            };
+            // swap the longest edge of the cycle for the new one
+            self.edges.remove(&self.up[longest]);
+            self.parent[longest] = None;
+            self.link(edge.1, v, edge);
+        }
+        v
+    }
+}
+
+#[aoc(day8, part1)]
+pub fn part1(input: &Input) -> usize {
+    part1_testable(input, 1000)
+}
+
+pub fn part1_testable(input: &Input, n: usize) -> usize {
+    part1_with_metric(input, n, Metric::Euclidean)
+}
+
+pub fn part1_with_metric<const D: usize>(input: &[Point<D>], n: usize, metric: Metric) -> usize {
+    let diset = Dendrogram::build_partial(input, metric, n).after_edges(n);
+    let mut sizes: Vec<usize> = diset.component_sizes().collect();
+    sizes.sort_unstable_by(|a, b| b.cmp(a));
+    sizes.into_iter().take(3).product()
+}
+
+#[aoc(day8, part2)]
+pub fn part2(input: &Input) -> usize {
+    part2_with_metric(input, Metric::Euclidean)
+}
+
+pub fn part2_with_metric<const D: usize>(input: &[Point<D>], metric: Metric) -> usize {
+    let dendrogram = Dendrogram::build(input, metric);
+    let (p1, p2) = dendrogram.last_merge().unwrap().pair;
+    input[p1].coords[0] as usize * input[p2].coords[0] as usize
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::rng::Rng;
+    use std::time::Instant;
 
     const EXAMPLE: &str = r#"162,817,812
 57,618,57
@@ -187,4 +743,283 @@ mod test {
     fn part2_example() {
         assert_eq!(part2(&parse(EXAMPLE)), 25272);
     }
+
+    fn random_points<const D: usize>(n: usize, range: u64, seed: u64) -> Vec<Point<D>> {
+        let mut rng = Rng::new(seed);
+        let mut coord = || rng.below(range) as i32 - (range / 2) as i32;
+        (0..n)
+            .map(|_| Point::new(std::array::from_fn(|_| coord())))
+            .collect()
+    }
+
+    // plain kruskal over every pair
+    fn brute_force<const D: usize>(points: &[Point<D>], n: usize) -> (usize, usize) {
+        let mut all_pairs = Vec::new();
+        for i in 0..points.len() {
+            for j in (i + 1)..points.len() {
+                all_pairs.push((points[i].squared_distance(&points[j]), i, j));
+            }
+        }
+        all_pairs.sort_unstable();
+        let mut diset = DisjointSet::new(points.len());
+        let mut part1 = 0;
+        let mut last_pair = (0, 0);
+        for (edges, &(_, i, j)) in all_pairs.iter().enumerate() {
+            if diset.union(i, j) {
+                last_pair = (i, j);
+            }
+            if edges + 1 == n {
+                let mut sizes: Vec<usize> = diset.component_sizes().collect();
+                sizes.sort_unstable_by(|a, b| b.cmp(a));
+                part1 = sizes.into_iter().take(3).product();
+            }
+        }
+        let (p1, p2) = last_pair;
+        let part2 = points[p1].coords[0] as usize * points[p2].coords[0] as usize;
+        (part1, part2)
+    }
+
+    fn check_dimension<const D: usize>(seed: u64) {
+        // part2 multiplies x coordinates as usize
+        let points: Vec<Point<D>> = random_points(200, 1000, seed)
+            .into_iter()
+            .map(|p: Point<D>| Point::new(p.coords.map(i32::abs)))
+            .collect();
+        let expected = brute_force(&points, 150);
+        assert_eq!(
+            part1_with_metric(&points, 150, Metric::Euclidean),
+            expected.0
+        );
+        assert_eq!(part2_with_metric(&points, Metric::Euclidean), expected.1);
+    }
+
+    #[test]
+    fn other_dimensions() {
+        check_dimension::<1>(1);
+        check_dimension::<2>(2);
+        check_dimension::<5>(5);
+
+        let points = parse_points::<2>("1,2\n3,4\n-5,6").unwrap();
+        assert_eq!(points[2], Point::new([-5, 6]));
+        assert_eq!(dimension("1,2,3,4,5\n6,7,8,9,10"), Ok(5));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            parse_points::<3>("1,2,3\n4,5\n6,7,8"),
+            Err(ParseError::MixedDimensions {
+                line: 2,
+                expected: 3,
+                found: 2
+            })
+        );
+        assert_eq!(
+            parse_points::<3>("1,2\n4,5"),
+            Err(ParseError::WrongDimension {
+                expected: 3,
+                found: 2
+            })
+        );
+        assert_eq!(
+            parse_points::<2>("1,2\n4,x"),
+            Err(ParseError::InvalidCoordinate { line: 2 })
+        );
+    }
+
+    #[test]
+    fn pair_stream_matches_all_pairs() {
+        // a small range so there are plenty of equal distances
+        let points: Vec<Point> = random_points(300, 40, 1);
+        for metric in [Metric::Euclidean, Metric::Manhattan, Metric::Chebyshev] {
+            let mut all_pairs = Vec::new();
+            for i in 0..points.len() {
+                for j in (i + 1)..points.len() {
+                    all_pairs.push((metric.distance(&points[i], &points[j]), i, j));
+                }
+            }
+            all_pairs.sort_unstable();
+            let streamed: Vec<_> = PairStream::new(&points, metric).collect();
+            assert_eq!(streamed, all_pairs, "{metric:?}");
+        }
+    }
+
+    #[test]
+    fn equal_distances_by_index() {
+        let points = parse("0,0,0\n3,0,0\n0,3,0\n0,0,3");
+        let pairs: Vec<_> = PairStream::new(&points, Metric::Euclidean).collect();
+        assert_eq!(
+            pairs,
+            vec![
+                (9, 0, 1),
+                (9, 0, 2),
+                (9, 0, 3),
+                (18, 1, 2),
+                (18, 1, 3),
+                (18, 2, 3)
+            ]
+        );
+        let dist = |m: Metric| m.distance(&points[1], &points[2]);
+        assert_eq!(dist(Metric::Manhattan), 6);
+        assert_eq!(dist(Metric::Chebyshev), 3);
+    }
+
+    #[test]
+    fn extreme_coordinates() {
+        let (min, max) = (i32::MIN, i32::MAX);
+        let points = vec![
+            Point::new([min, min, min]),
+            Point::new([max, max, max]),
+            Point::new([max, max, min]),
+            Point::new([0, 0, 0]),
+        ];
+        let span = (max as i128 - min as i128).pow(2);
+        assert!(3 * span > i64::MAX as i128);
+        assert_eq!(points[0].squared_distance(&points[1]), 3 * span);
+        assert_eq!(
+            Metric::Manhattan.distance(&points[0], &points[1]),
+            3 * (span.isqrt())
+        );
+
+        let mut all_pairs = Vec::new();
+        for i in 0..points.len() {
+            for j in (i + 1)..points.len() {
+                all_pairs.push((points[i].squared_distance(&points[j]), i, j));
+            }
+        }
+        all_pairs.sort_unstable();
+        let streamed: Vec<_> = PairStream::new(&points, Metric::Euclidean).collect();
+        assert_eq!(streamed, all_pairs);
+        let last = Dendrogram::build(&points, Metric::Euclidean)
+            .last_merge()
+            .copied();
+        assert_eq!(last.map(|m| m.pair), Some((0, 3)));
+    }
+
+    #[test]
+    fn dendrogram_cuts() {
+        let points = parse(EXAMPLE);
+        let dendrogram = Dendrogram::build(&points, Metric::Euclidean);
+        assert_eq!(dendrogram.merges.len(), points.len() - 1);
+
+        let first = dendrogram.merges[0];
+        assert_eq!(first.clusters, (0, 19));
+        assert_eq!(first.size, 2);
+        assert_eq!(dendrogram.merges.last().unwrap().size, points.len());
+
+        // replaying the pairs one by one gives the same clusterings
+        let mut diset = DisjointSet::new(points.len());
+        let mut pairs = PairStream::new(&points, Metric::Euclidean);
+        for edges in 1..=points.len() * (points.len() - 1) / 2 {
+            let (distance, p1, p2) = pairs.next().unwrap();
+            diset.union(p1, p2);
+            let after = dendrogram.after_edges(edges);
+            assert_eq!(after.components(), diset.components());
+            assert_eq!(
+                dendrogram.at_distance(distance).components(),
+                diset.components()
+            );
+        }
+
+        for k in 1..=points.len() {
+            assert_eq!(dendrogram.with_clusters(k).components(), k);
+        }
+        assert_eq!(dendrogram.with_clusters(0).components(), 1);
+        let mut sizes: Vec<usize> = dendrogram.after_edges(10).component_sizes().collect();
+        sizes.sort_unstable_by(|a, b| b.cmp(a));
+        assert_eq!(sizes[..3], [5, 4, 2]);
+    }
+
+    #[test]
+    fn online_insertion() {
+        // the same tree as a batch build over the boxes so far
+        fn same_as_batch<const D: usize>(mst: &OnlineMst<D>) {
+            let dendrogram = Dendrogram::build(mst.points(), Metric::Euclidean);
+            let mut edges: Vec<_> = dendrogram
+                .merges
+                .iter()
+                .map(|m| (m.distance, m.pair.0, m.pair.1))
+                .collect();
+            edges.sort_unstable();
+            assert_eq!(mst.edges().collect::<Vec<_>>(), edges);
+            assert_eq!(
+                mst.connecting_edge(),
+                dendrogram.last_merge().map(|m| m.pair)
+            );
+        }
+        let check = |points: &[Point<2>], cell_size: i64| {
+            let mut mst = OnlineMst::new(Metric::Euclidean, cell_size);
+            for (n, &p) in points.iter().enumerate() {
+                assert_eq!(mst.insert(p), n);
+                same_as_batch(&mst);
+            }
+        };
+        // plenty of ties on a small range
+        check(&random_points(150, 20, 3), 2);
+        check(&random_points(150, 10_000, 4), 800);
+
+        let points = parse(EXAMPLE);
+        let mut mst = OnlineMst::from_points(&points[..10], Metric::Euclidean);
+        same_as_batch(&mst);
+        for &p in &points[10..] {
+            mst.insert(p);
+            same_as_batch(&mst);
+        }
+        let (p1, p2) = mst.connecting_edge().unwrap();
+        let pts = mst.points();
+        assert_eq!(pts[p1].coords[0] * pts[p2].coords[0], 25272);
+
+        // an outlier makes the longest edge huge, the boxes after it are still cheap to add
+        let mut mst = OnlineMst::new(Metric::Euclidean, 100);
+        mst.insert(Point::new([20_000, 20_000, 20_000]));
+        for p in random_points::<3>(200, 1000, 5) {
+            mst.insert(p);
+        }
+        same_as_batch(&mst);
+
+        // a long chain, where the tree is a single path
+        let chain: Vec<Point<2>> = (0..20_000).map(|x| Point::new([x, 0])).collect();
+        let mut mst = OnlineMst::from_points(&chain, Metric::Euclidean);
+        assert_eq!(mst.edges().count(), chain.len() - 1);
+        mst.insert(Point::new([20_000, 0]));
+        assert_eq!(mst.connecting_edge(), Some((19_999, 20_000)));
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_100k_points() {
+        // cargo test --release bench_100k_points -- --ignored
+        let points = |n| -> Vec<Point> {
+            random_points(n, 1_000_000, 42)
+                .into_iter()
+                .map(|p: Point| Point::new(p.coords.map(i32::abs)))
+                .collect()
+        };
+        // sorting every pair is still possible with 5k points
+        let small = points(5_000);
+        let start = Instant::now();
+        let eager = brute_force(&small, 1000);
+        let eager_time = start.elapsed();
+        let start = Instant::now();
+        let lazy = (part1_testable(&small, 1000), part2(&small));
+        let lazy_time = start.elapsed();
+        assert_eq!(lazy, eager);
+        assert!(
+            lazy_time < eager_time,
+            "5k points: stream {lazy_time:?}, sort {eager_time:?}"
+        );
+
+        // but not with 100k, that would be 5 billion pairs: the sort takes at least
+        // 400 times longer with 20 times the points
+        let large = points(100_000);
+        let start = Instant::now();
+        part1_testable(&large, 1000);
+        part2(&large);
+        let large_time = start.elapsed();
+        let projected = eager_time * 400;
+        assert!(
+            large_time * 10 < projected,
+            "100k points: stream {large_time:?}, sort at least {projected:?}"
+        );
+    }
 }
@@ -1,108 +1,653 @@
+use std::{cmp::Reverse, collections::BinaryHeap, fmt};
+
 use aoc_runner_derive::{aoc, aoc_generator};
 use aoc_utils::point::Point;
 
-type Input = Vec<Point>;
+type Input = Vec<Loop>;
+
+fn parse_point(l: &str) -> Point {
+    let (x, y) = l.split_once(',').unwrap();
+    let x: i64 = x.parse().unwrap();
+    let y: i64 = y.parse().unwrap();
+    Point::new(x, y)
+}
+
+/// A single loop of red tiles.
+pub fn parse(input: &str) -> Vec<Point> {
+    input.lines().map(parse_point).collect()
+}
+
+/// Red tiles of a loop, with the input line of the first one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loop {
+    pub points: Vec<Point>,
+    pub first_line: usize,
+}
 
+/// Several loops separated by blank lines, loops inside another one being holes in it.
 #[aoc_generator(day9)]
-pub fn parse(input: &str) -> Input {
-    let mut res = Vec::new();
-    for l in input.lines() {
-        let (x, y) = l.split_once(',').unwrap();
-        let x: i64 = x.parse().unwrap();
-        let y: i64 = y.parse().unwrap();
-        res.push(Point::new(x, y));
+pub fn parse_loops(input: &str) -> Input {
+    let mut res: Vec<Loop> = Vec::new();
+    let mut in_loop = false;
+    for (i, l) in input.lines().enumerate() {
+        let l = l.trim();
+        if l.is_empty() {
+            in_loop = false;
+            continue;
+        }
+        if !in_loop {
+            res.push(Loop {
+                points: Vec::new(),
+                first_line: i + 1,
+            });
+            in_loop = true;
+        }
+        res.last_mut().unwrap().points.push(parse_point(l));
     }
     res
 }
 
+/// Which way the loop turns, as displayed with y pointing down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Edges are given by the lines of the two points they join.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolygonError {
+    TooFewPoints {
+        found: usize,
+    },
+    DuplicatePoint {
+        lines: (usize, usize),
+    },
+    /// Only for what needs a rectilinear polygon.
+    DiagonalEdge {
+        lines: (usize, usize),
+    },
+    /// Two edges touch somewhere else than at the point they share, if any.
+    SelfIntersection {
+        first: (usize, usize),
+        second: (usize, usize),
+    },
+}
+
+impl fmt::Display for PolygonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolygonError::TooFewPoints { found } => {
+                write!(f, "expected at least 3 points, found {found}")
+            }
+            PolygonError::DuplicatePoint { lines: (a, b) } => {
+                write!(f, "lines {a} and {b}: same point twice in a row")
+            }
+            PolygonError::DiagonalEdge { lines: (a, b) } => {
+                write!(
+                    f,
+                    "lines {a} and {b}: edge is neither horizontal nor vertical"
+                )
+            }
+            PolygonError::SelfIntersection {
+                first: (a, b),
+                second: (c, d),
+            } => write!(f, "edges {a}-{b} and {c}-{d} intersect"),
+        }
+    }
+}
+
+impl std::error::Error for PolygonError {}
+
+impl PolygonError {
+    /// Same error for a loop starting `by` lines further down.
+    fn shifted(self, by: usize) -> Self {
+        let shift = |(a, b): (usize, usize)| (a + by, b + by);
+        match self {
+            PolygonError::TooFewPoints { found } => PolygonError::TooFewPoints { found },
+            PolygonError::DuplicatePoint { lines } => PolygonError::DuplicatePoint {
+                lines: shift(lines),
+            },
+            PolygonError::DiagonalEdge { lines } => PolygonError::DiagonalEdge {
+                lines: shift(lines),
+            },
+            PolygonError::SelfIntersection { first, second } => PolygonError::SelfIntersection {
+                first: shift(first),
+                second: shift(second),
+            },
+        }
+    }
+}
+
+/// Twice the signed area of the triangle `o`, `a`, `b`: positive when turning clockwise
+/// with y pointing down, zero when they are aligned.
+fn cross(o: Point, a: Point, b: Point) -> i128 {
+    (a.x - o.x) as i128 * (b.y - o.y) as i128 - (a.y - o.y) as i128 * (b.x - o.x) as i128
+}
+
+/// Checks that `p` is on the segment `a`-`b`, ends included.
+fn on_segment(a: Point, b: Point, p: Point) -> bool {
+    cross(a, b, p) == 0
+        && a.x.min(b.x) <= p.x
+        && p.x <= a.x.max(b.x)
+        && a.y.min(b.y) <= p.y
+        && p.y <= a.y.max(b.y)
+}
+
+/// Checks that the segments have at least one point in common.
+fn segments_intersect(a: Point, b: Point, c: Point, d: Point) -> bool {
+    let (d1, d2) = (cross(a, b, c).signum(), cross(a, b, d).signum());
+    let (d3, d4) = (cross(c, d, a).signum(), cross(c, d, b).signum());
+    (d1 * d2 < 0 && d3 * d4 < 0)
+        || on_segment(a, b, c)
+        || on_segment(a, b, d)
+        || on_segment(c, d, a)
+        || on_segment(c, d, b)
+}
+
+/// Checks that the red tiles form a closed loop that doesn't cross itself.
+///
+/// The self-intersection check is quadratic in the number of edges.
+pub fn validate(input: &[Point]) -> Result<Orientation, PolygonError> {
+    let n = input.len();
+    if n < 3 {
+        return Err(PolygonError::TooFewPoints { found: n });
+    }
+    // edge i goes from point i to point i + 1, on lines i + 1 and i + 2
+    let lines = |i: usize| (i + 1, (i + 1) % n + 1);
+    for i in 0..n {
+        let (p1, p2) = (input[i], input[(i + 1) % n]);
+        if p1 == p2 {
+            return Err(PolygonError::DuplicatePoint { lines: lines(i) });
+        }
+    }
+
+    let edges = polygon_edges(input);
+    for i in 0..n {
+        for j in i + 1..n {
+            let intersect = if j == i + 1 || (i == 0 && j == n - 1) {
+                // consecutive edges only share a point, unless one goes back over the other
+                let (a, b, c) = if j == i + 1 {
+                    (input[i], input[j], input[(j + 1) % n])
+                } else {
+                    (input[j], input[0], input[1])
+                };
+                let (d1, d2) = (b - a, c - b);
+                let dot = d1.x as i128 * d2.x as i128 + d1.y as i128 * d2.y as i128;
+                cross(a, b, c) == 0 && dot < 0
+            } else {
+                let ((a, b), (c, d)) = (edges[i], edges[j]);
+                segments_intersect(a, b, c, d)
+            };
+            if intersect {
+                return Err(PolygonError::SelfIntersection {
+                    first: lines(i),
+                    second: lines(j),
+                });
+            }
+        }
+    }
+
+    // shoelace formula, positive when turning clockwise with y pointing down
+    let origin = Point::new(0, 0);
+    let area2: i128 = (0..n)
+        .map(|i| cross(origin, input[i], input[(i + 1) % n]))
+        .sum();
+    Ok(if area2 > 0 {
+        Orientation::Clockwise
+    } else {
+        Orientation::CounterClockwise
+    })
+}
+
+/// Checks every loop, and that no two of them touch.
+pub fn validate_loops(loops: &[Loop]) -> Result<(), PolygonError> {
+    for l in loops {
+        validate(&l.points).map_err(|e| e.shifted(l.first_line - 1))?;
+    }
+
+    let edges: Vec<Vec<(Point, Point)>> = loops.iter().map(|l| polygon_edges(&l.points)).collect();
+    for i in 0..loops.len() {
+        for j in i + 1..loops.len() {
+            for (e1, &(a, b)) in edges[i].iter().enumerate() {
+                for (e2, &(c, d)) in edges[j].iter().enumerate() {
+                    if segments_intersect(a, b, c, d) {
+                        let (l1, l2) = (&loops[i], &loops[j]);
+                        let n1 = l1.points.len();
+                        let n2 = l2.points.len();
+                        return Err(PolygonError::SelfIntersection {
+                            first: (l1.first_line + e1, l1.first_line + (e1 + 1) % n1),
+                            second: (l2.first_line + e2, l2.first_line + (e2 + 1) % n2),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rectangle spanned by two tiles, usually red ones, bounds included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub corners: [Point; 2],
+    pub left: i64,
+    pub top: i64,
+    pub right: i64,
+    pub bottom: i64,
+}
+
+impl Rect {
+    pub fn new(p1: Point, p2: Point) -> Self {
+        Rect {
+            corners: [p1, p2],
+            left: p1.x.min(p2.x),
+            top: p1.y.min(p2.y),
+            right: p1.x.max(p2.x),
+            bottom: p1.y.max(p2.y),
+        }
+    }
+
+    pub fn area(&self) -> u64 {
+        self.left.abs_diff(self.right + 1) * self.top.abs_diff(self.bottom + 1)
+    }
+}
+
+/// The `k` largest rectangles spanned by two red tiles and accepted by `valid`, largest first.
+/// Ties go to the earliest pair of tiles in the input.
+fn largest_valid_rects(
+    input: &[Point],
+    k: usize,
+    mut valid: impl FnMut(&Rect) -> bool,
+) -> Vec<Rect> {
+    if k == 0 {
+        return Vec::new();
+    }
+    // min-heap of the best pairs so far, the root being the first one to drop
+    let mut best = BinaryHeap::with_capacity(k + 1);
+    for (i, p1) in input.iter().enumerate() {
+        for (j, p2) in input.iter().enumerate().skip(i + 1) {
+            let rect = Rect::new(*p1, *p2);
+            let area = rect.area();
+            // optimization: skip if it would be dropped right away, before checking it
+            if best.len() == k {
+                let Reverse((min_area, _)) = best.peek().unwrap();
+                if area <= *min_area {
+                    continue;
+                }
+            }
+            if !valid(&rect) {
+                continue;
+            }
+            best.push(Reverse((area, Reverse((i, j)))));
+            if best.len() > k {
+                best.pop();
+            }
+        }
+    }
+    best.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((_, Reverse((i, j))))| Rect::new(input[i], input[j]))
+        .collect()
+}
+
+/// The `k` largest rectangles spanned by two red tiles, largest first.
+pub fn largest_rects(input: &[Point], k: usize) -> Vec<Rect> {
+    largest_valid_rects(input, k, |_| true)
+}
+
+/// The `k` largest rectangles spanned by two red tiles and made of red or green tiles only,
+/// largest first.
+pub fn largest_contained_rects(input: &[Point], k: usize) -> Result<Vec<Rect>, PolygonError> {
+    validate(input)?;
+    Ok(contained_rects(input, &polygon_edges(input), k, |_| true))
+}
+
+/// Same as `largest_contained_rects` for several loops, where tiles in a hole are not green
+/// but the ones on its loop are. A loop in a hole is an outer loop again.
+pub fn largest_region_rects(loops: &[Loop], k: usize) -> Result<Vec<Rect>, PolygonError> {
+    region_rects(loops, k, |_| true)
+}
+
+fn region_rects(
+    loops: &[Loop],
+    k: usize,
+    keep: impl Fn(&Rect) -> bool,
+) -> Result<Vec<Rect>, PolygonError> {
+    validate_loops(loops)?;
+    Ok(contained_rects(
+        &red_tiles(loops),
+        &region_edges(loops),
+        k,
+        keep,
+    ))
+}
+
+fn red_tiles(loops: &[Loop]) -> Vec<Point> {
+    loops
+        .iter()
+        .flat_map(|l| l.points.iter().copied())
+        .collect()
+}
+
+fn contained_rects(
+    points: &[Point],
+    edges: &[(Point, Point)],
+    k: usize,
+    keep: impl Fn(&Rect) -> bool,
+) -> Vec<Rect> {
+    if diagonal_edge(edges).is_some() {
+        return largest_valid_rects(points, k, |r| {
+            keep(r) && rect_is_fully_contained(edges, r.left, r.top, r.right, r.bottom)
+        });
+    }
+    let grid = CompressedGrid::new(edges);
+    largest_valid_rects(points, k, |r| {
+        keep(r) && grid.contains_rect(r.left, r.top, r.right, r.bottom)
+    })
+}
+
 #[aoc(day9, part1)]
 pub fn part1(input: &Input) -> usize {
-    let mut max_area = 0;
-    for (i, p1) in input.iter().enumerate() {
-        for p2 in input.iter().skip(i + 1) {
-            let x = p1.x.abs_diff(p2.x) + 1;
-            let y = p1.y.abs_diff(p2.y) + 1;
-            let area = x * y;
-            max_area = area.max(max_area);
+    largest_rects(&red_tiles(input), 1)
+        .first()
+        .map_or(0, Rect::area) as usize
+}
+
+/// Checks a point given in doubled coordinates (so that it can be halfway between two tiles)
+/// against the polygon, points on the boundary count as inside.
+///
+/// This uses the even-odd rule, so `edges` can hold several loops with holes.
+fn contains_doubled_point(edges: &[(Point, Point)], x2: i64, y2: i64) -> bool {
+    let p = Point::new(x2, y2);
+    let mut crossings = 0;
+    for (a, b) in edges {
+        let (a, b) = (Point::new(2 * a.x, 2 * a.y), Point::new(2 * b.x, 2 * b.y));
+        if on_segment(a, b, p) {
+            return true;
+        }
+        // cast a ray to the right, edges are half-open so that vertices count once
+        let (low, high) = if a.y < b.y { (a, b) } else { (b, a) };
+        if low.y <= p.y && p.y < high.y && cross(low, high, p) > 0 {
+            crossings += 1;
         }
     }
-    max_area as usize
+    crossings % 2 == 1
 }
 
-fn rect_is_fully_contained(
+/// Checks that every tile of the rectangle is red or green, like `CompressedGrid::contains_rect`
+/// which is the fast version for rectilinear polygons.
+///
+/// A tile is green when it's inside the polygon or on its boundary, checked with exact integer
+/// arithmetic so edges don't need to be horizontal or vertical. Each row of the rectangle is
+/// cut where edges cross it, and the tiles between two cuts are all inside or all outside,
+/// so this takes O(height * edges^2).
+pub fn rect_is_fully_contained(
     edges: &[(Point, Point)],
     rect_left: i64,
     rect_top: i64,
     rect_right: i64,
     rect_bottom: i64,
 ) -> bool {
-    // for each edge, check if it overlaps with the rectangle
-    // NOTE: this exploits the fact that all edges are axis-aligned
-    for (edge_low, edge_high) in edges {
-        // if x range overlap && y range overlap
-        if rect_left < edge_high.x
-            && rect_right > edge_low.x
-            && rect_top < edge_high.y
-            && rect_bottom > edge_low.y
-        {
-            return false;
+    (rect_top..=rect_bottom).all(|y| row_is_contained(edges, y, rect_left, rect_right))
+}
+
+fn row_is_contained(edges: &[(Point, Point)], y: i64, left: i64, right: i64) -> bool {
+    // first tile of every run of tiles between two boundary points
+    let mut starts = vec![left];
+    for &(a, b) in edges {
+        if a.y.min(b.y) > y || a.y.max(b.y) < y {
+            continue;
+        }
+        if a.y == b.y {
+            starts.extend([a.x + 1, b.x + 1]);
+        } else {
+            // floor of the x where the edge crosses the row
+            let num = (b.x - a.x) as i128 * (y - a.y) as i128;
+            let den = (b.y - a.y) as i128;
+            let x = a.x as i128 + (num * den.signum()).div_euclid(den.abs());
+            starts.push(x as i64 + 1);
         }
     }
-    true
+    starts.retain(|&x| left <= x && x <= right);
+    starts.sort_unstable();
+    starts.dedup();
+    starts
+        .into_iter()
+        .all(|x| contains_doubled_point(edges, 2 * x, 2 * y))
 }
 
-#[aoc(day9, part2)]
-pub fn part2(input: &Input) -> usize {
-    // part2 can roughly be generalized as the following:
-    // given a set of points forming a polygon, we need to find if a given rectangle
-    // fits completely inside the polygon
-
-    // compute normalized edges for the polygon
-    // horizontal = left to right
-    // vertical = bottom to top
+/// Normalized edges of the polygon, the last one closing the loop:
+/// horizontal ones go left to right, vertical ones top to bottom,
+/// and diagonal ones left to right.
+pub fn polygon_edges(input: &[Point]) -> Vec<(Point, Point)> {
     let mut edges = Vec::with_capacity(input.len());
-    for win in input.as_slice().windows(2) {
-        let [p1, p2] = win else { unreachable!() };
-        let e1 = Point::new(p1.x.min(p2.x), p1.y.min(p2.y));
-        let e2 = Point::new(p1.x.max(p2.x), p1.y.max(p2.y));
-        edges.push((e1, e2));
-    }
-    let last_p = input.last().unwrap();
-    let first_p = input.first().unwrap();
-    edges.push((
-        Point::new(last_p.x.min(first_p.x), last_p.y.min(first_p.y)),
-        Point::new(last_p.x.max(first_p.x), last_p.y.max(first_p.y)),
-    ));
-
-    let mut max_area = 0;
     for (i, p1) in input.iter().enumerate() {
-        for p2 in input.iter().skip(i + 1) {
-            let x = p1.x.abs_diff(p2.x) + 1;
-            let y = p1.y.abs_diff(p2.y) + 1;
-            let area = x * y;
-            // optimization: skip if area is already less than max_area
-            if area <= max_area {
-                continue;
+        let p2 = &input[(i + 1) % input.len()];
+        if (p1.x, p1.y) <= (p2.x, p2.y) {
+            edges.push((*p1, *p2));
+        } else {
+            edges.push((*p2, *p1));
+        }
+    }
+    edges
+}
+
+/// Edges of every loop, the ones of a hole included.
+pub fn region_edges(loops: &[Loop]) -> Vec<(Point, Point)> {
+    loops
+        .iter()
+        .flat_map(|l| polygon_edges(&l.points))
+        .collect()
+}
+
+/// Index of the first edge that is neither horizontal nor vertical.
+fn diagonal_edge(edges: &[(Point, Point)]) -> Option<usize> {
+    edges.iter().position(|(a, b)| a.x != b.x && a.y != b.y)
+}
+
+/// Rectilinear polygon rasterised on a compressed grid, with a 2D prefix sum
+/// table of the tiles outside of it. Edges must be horizontal or vertical, and can come
+/// from several loops as with `region_edges`.
+///
+/// Every distinct x (resp. y) of the red tiles gets its own column (resp. row),
+/// and every run of tiles between two of them is merged into a single one.
+/// All the tiles merged into a cell are on the same side of the polygon.
+pub struct CompressedGrid {
+    xs: Vec<i64>,
+    ys: Vec<i64>,
+    width: usize,
+    height: usize,
+    outside: Vec<bool>,
+    // prefix[y * (width + 1) + x]: non-empty outside cells in [0, x) x [0, y),
+    // which can be more than a u32 holds with tens of thousands of red tiles
+    prefix: Vec<u64>,
+}
+
+/// First and last tile of a column (resp. row) of the compressed grid, not counting the
+/// padding ones. Empty runs end before they start.
+fn tile_run(coords: &[i64], c: usize) -> (i64, i64) {
+    if c % 2 == 1 {
+        (coords[c / 2], coords[c / 2])
+    } else {
+        (coords[c / 2 - 1] + 1, coords[c / 2] - 1)
+    }
+}
+
+impl CompressedGrid {
+    pub fn new(edges: &[(Point, Point)]) -> Self {
+        let mut xs: Vec<i64> = edges.iter().flat_map(|(a, b)| [a.x, b.x]).collect();
+        let mut ys: Vec<i64> = edges.iter().flat_map(|(a, b)| [a.y, b.y]).collect();
+        xs.sort_unstable();
+        xs.dedup();
+        ys.sort_unstable();
+        ys.dedup();
+        // coordinate k is at 2k + 1, with the (possibly empty) runs between them at even indices
+        // and an extra run all around
+        let width = 2 * xs.len() + 1;
+        let height = 2 * ys.len() + 1;
+        let col = |x: i64| 2 * xs.binary_search(&x).unwrap() + 1;
+        let row = |y: i64| 2 * ys.binary_search(&y).unwrap() + 1;
+
+        let mut boundary = vec![false; width * height];
+        // vertical edges a ray going right from the cell crosses, half-open like
+        // `contains_doubled_point` so that vertices count once
+        let mut crossing = vec![false; width * height];
+        for (low, high) in edges {
+            for cy in row(low.y)..=row(high.y) {
+                for cx in col(low.x)..=col(high.x) {
+                    boundary[cy * width + cx] = true;
+                }
             }
-            // skip lines
-            if x == 1 || y == 1 {
-                continue;
+            if low.x == high.x {
+                for cy in row(low.y)..row(high.y) {
+                    crossing[cy * width + col(low.x)] ^= true;
+                }
+            }
+        }
+
+        // every face of the plane is outside or inside as a whole, so ray casting from each
+        // cell gives the same answer as for any of its tiles
+        let mut outside = vec![false; width * height];
+        for cy in 0..height {
+            let mut inside = false;
+            for cx in (0..width).rev() {
+                let idx = cy * width + cx;
+                outside[idx] = !boundary[idx] && !inside;
+                inside ^= crossing[idx];
+            }
+        }
+        // the prefix table is the largest of them all, don't keep the others around with it
+        drop(boundary);
+        drop(crossing);
+
+        // runs between two consecutive coordinates can be empty, they don't count
+        let is_empty = |coords: &[i64], c: usize| {
+            c.is_multiple_of(2)
+                && c > 0
+                && c < 2 * coords.len()
+                && coords[c / 2] - coords[c / 2 - 1] == 1
+        };
+        let mut prefix = vec![0u64; (width + 1) * (height + 1)];
+        for cy in 0..height {
+            for cx in 0..width {
+                let count = outside[cy * width + cx] && !is_empty(&xs, cx) && !is_empty(&ys, cy);
+                prefix[(cy + 1) * (width + 1) + cx + 1] = count as u64
+                    + prefix[cy * (width + 1) + cx + 1]
+                    + prefix[(cy + 1) * (width + 1) + cx]
+                    - prefix[cy * (width + 1) + cx];
+            }
+        }
+
+        CompressedGrid {
+            xs,
+            ys,
+            width,
+            height,
+            outside,
+            prefix,
+        }
+    }
+
+    /// Largest rectangle made of red or green tiles only, whatever its corners.
+    ///
+    /// Every cell is either fully inside or outside, so the best rectangle is made of whole
+    /// cells, which a weighted largest-rectangle-in-histogram finds row by row.
+    pub fn largest_inside_rect(&self) -> Rect {
+        // empty runs don't hold any tile, two cells on each side of one are actually adjacent
+        let cols: Vec<usize> = (1..self.width - 1)
+            .filter(|&cx| tile_run(&self.xs, cx).0 <= tile_run(&self.xs, cx).1)
+            .collect();
+        let rows: Vec<usize> = (1..self.height - 1)
+            .filter(|&cy| tile_run(&self.ys, cy).0 <= tile_run(&self.ys, cy).1)
+            .collect();
+        let mut col_start = vec![0];
+        for &cx in &cols {
+            let (first, last) = tile_run(&self.xs, cx);
+            col_start.push(col_start.last().unwrap() + last - first + 1);
+        }
+
+        let mut best: Option<(i64, usize, usize, i64, usize)> = None;
+        // tiles from the current row up to the first outside cell above it, for every column
+        let mut heights = vec![0; cols.len()];
+        let mut stack: Vec<usize> = Vec::with_capacity(cols.len());
+        for (r, &cy) in rows.iter().enumerate() {
+            let (first, last) = tile_run(&self.ys, cy);
+            for (h, &cx) in heights.iter_mut().zip(&cols) {
+                *h = if self.outside[cy * self.width + cx] {
+                    0
+                } else {
+                    *h + last - first + 1
+                };
             }
-            let rect_left = p1.x.min(p2.x);
-            let rect_top = p1.y.min(p2.y);
-            let rect_right = p1.x.max(p2.x);
-            let rect_bottom = p1.y.max(p2.y);
-            if rect_is_fully_contained(&edges, rect_left, rect_top, rect_right, rect_bottom) {
-                max_area = max_area.max(area);
+            stack.clear();
+            for i in 0..=cols.len() {
+                let h = heights.get(i).copied().unwrap_or(0);
+                while let Some(&top) = stack.last() {
+                    if heights[top] < h {
+                        break;
+                    }
+                    stack.pop();
+                    // heights[top] is the lowest one between the next item on the stack and i
+                    let left = stack.last().map_or(0, |&l| l + 1);
+                    let area = heights[top] * (col_start[i] - col_start[left]);
+                    if best.is_none_or(|b| area > b.0) {
+                        best = Some((area, left, i - 1, heights[top], r));
+                    }
+                }
+                stack.push(i);
             }
         }
+
+        let (_, left, right, height, r) = best.unwrap();
+        let bottom = tile_run(&self.ys, rows[r]).1;
+        Rect::new(
+            Point::new(tile_run(&self.xs, cols[left]).0, bottom - height + 1),
+            Point::new(tile_run(&self.xs, cols[right]).1, bottom),
+        )
     }
-    max_area as usize
+
+    /// Checks in O(1) that every tile of the rectangle is red or green.
+    ///
+    /// The bounds must be coordinates of red tiles.
+    pub fn contains_rect(&self, left: i64, top: i64, right: i64, bottom: i64) -> bool {
+        let col = |x: i64| 2 * self.xs.binary_search(&x).unwrap() + 1;
+        let row = |y: i64| 2 * self.ys.binary_search(&y).unwrap() + 1;
+        let (x1, x2, y1, y2) = (col(left), col(right) + 1, row(top), row(bottom) + 1);
+        let at = |x: usize, y: usize| self.prefix[y * (self.width + 1) + x];
+        debug_assert!(x2 <= self.width && y2 <= self.height);
+        at(x2, y2) + at(x1, y1) - at(x1, y2) - at(x2, y1) == 0
+    }
+}
+
+/// Largest rectangle made of red or green tiles only, not necessarily spanned by red tiles.
+///
+/// The polygon must be rectilinear.
+pub fn largest_inside_rect(input: &[Point]) -> Result<Rect, PolygonError> {
+    validate(input)?;
+    let edges = polygon_edges(input);
+    if let Some(i) = diagonal_edge(&edges) {
+        return Err(PolygonError::DiagonalEdge {
+            lines: (i + 1, (i + 1) % input.len() + 1),
+        });
+    }
+    Ok(CompressedGrid::new(&edges).largest_inside_rect())
+}
+
+#[aoc(day9, part2)]
+pub fn part2(input: &Input) -> Result<usize, PolygonError> {
+    // part2 can roughly be generalized as the following:
+    // given a set of points forming a polygon, we need to find if a given rectangle
+    // fits completely inside the polygon
+    // skip lines
+    let best = region_rects(input, 1, |r| r.left != r.right && r.top != r.bottom)?;
+    Ok(best.first().map_or(0, Rect::area) as usize)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use gxhash::{HashSet, HashSetExt};
 
     const EXAMPLE: &str = r#"7,1
 11,1
@@ -115,11 +660,442 @@ mod test {
 
     #[test]
     fn part1_example() {
-        assert_eq!(part1(&parse(EXAMPLE)), 50);
+        assert_eq!(part1(&parse_loops(EXAMPLE)), 50);
     }
 
     #[test]
     fn part2_example() {
-        assert_eq!(part2(&parse(EXAMPLE)), 24);
+        assert_eq!(part2(&parse_loops(EXAMPLE)), Ok(24));
+    }
+
+    const SKIPPED_LINES: &str = "0,0\n30,0\n29,1\n14,1\n14,3\n12,3\n12,1\n1,1";
+
+    #[test]
+    fn part2_skips_lines() {
+        // a thin bar with slanted ends, and a small box under it
+        let input = parse(SKIPPED_LINES);
+        let best = largest_contained_rects(&input, 1).unwrap()[0];
+        assert_eq!(best.corners, [Point::new(0, 0), Point::new(30, 0)]);
+        assert_eq!(part2(&parse_loops(SKIPPED_LINES)), Ok(9));
+        assert_eq!(part2_naive(&input), 9);
+    }
+
+    const U_SHAPE: &str = r#"0,0
+20,0
+20,10
+18,10
+18,2
+2,2
+2,10
+0,10"#;
+
+    const C_SHAPE: &str = r#"0,0
+10,0
+10,2
+2,2
+2,18
+10,18
+10,20
+0,20"#;
+
+    fn part2_naive(input: &[Point]) -> usize {
+        let edges = polygon_edges(input);
+        let mut max_area = 0;
+        for (i, p1) in input.iter().enumerate() {
+            for p2 in input.iter().skip(i + 1) {
+                let (left, right) = (p1.x.min(p2.x), p1.x.max(p2.x));
+                let (top, bottom) = (p1.y.min(p2.y), p1.y.max(p2.y));
+                if left == right || top == bottom {
+                    continue;
+                }
+                if rect_is_fully_contained(&edges, left, top, right, bottom) {
+                    let area = (right - left + 1) * (bottom - top + 1);
+                    max_area = max_area.max(area as usize);
+                }
+            }
+        }
+        max_area
+    }
+
+    #[test]
+    fn best_rects() {
+        let input = parse(EXAMPLE);
+        let best = largest_rects(&input, 1)[0];
+        assert_eq!(best.corners, [Point::new(11, 1), Point::new(2, 5)]);
+        assert_eq!(
+            (best.left, best.top, best.right, best.bottom),
+            (2, 1, 11, 5)
+        );
+        assert_eq!(best.area(), 50);
+
+        let best = largest_contained_rects(&input, 1).unwrap()[0];
+        assert_eq!(best.corners, [Point::new(9, 5), Point::new(2, 3)]);
+        assert_eq!(best.area(), 24);
+    }
+
+    #[test]
+    fn top_k_rects() {
+        let input = parse(EXAMPLE);
+        // every pair, sorted by decreasing area then input order
+        let mut all: Vec<(u64, usize, usize)> = Vec::new();
+        for i in 0..input.len() {
+            for j in i + 1..input.len() {
+                all.push((Rect::new(input[i], input[j]).area(), i, j));
+            }
+        }
+        all.sort_by_key(|&(area, i, j)| (Reverse(area), i, j));
+        for k in [0, 1, 5, all.len(), all.len() + 3] {
+            let rects = largest_rects(&input, k);
+            let expected: Vec<Rect> = all
+                .iter()
+                .take(k)
+                .map(|&(_, i, j)| Rect::new(input[i], input[j]))
+                .collect();
+            assert_eq!(rects, expected, "k = {k}");
+        }
+
+        let areas: Vec<u64> = largest_contained_rects(&input, 4)
+            .unwrap()
+            .iter()
+            .map(Rect::area)
+            .collect();
+        assert_eq!(areas, vec![24, 21, 18, 15]);
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(validate(&parse(EXAMPLE)), Ok(Orientation::Clockwise));
+        let mut reversed = parse(U_SHAPE);
+        reversed.reverse();
+        assert_eq!(validate(&reversed), Ok(Orientation::CounterClockwise));
+
+        assert_eq!(
+            validate(&parse("0,0\n5,0")),
+            Err(PolygonError::TooFewPoints { found: 2 })
+        );
+        assert_eq!(
+            validate(&parse("0,0\n5,0\n5,5\n5,5\n0,5")),
+            Err(PolygonError::DuplicatePoint { lines: (3, 4) })
+        );
+        assert!(validate(&parse("0,0\n5,0\n5,5\n1,5\n0,4")).is_ok());
+        // crossing diagonals
+        assert_eq!(
+            validate(&parse("0,0\n5,5\n5,0\n0,5")),
+            Err(PolygonError::SelfIntersection {
+                first: (1, 2),
+                second: (3, 4)
+            })
+        );
+        // figure eight
+        assert_eq!(
+            validate(&parse("0,0\n4,0\n4,8\n8,8\n8,4\n0,4")),
+            Err(PolygonError::SelfIntersection {
+                first: (2, 3),
+                second: (5, 6)
+            })
+        );
+        // going back over the previous edge
+        assert_eq!(
+            validate(&parse("0,0\n6,0\n3,0\n3,5\n0,5")),
+            Err(PolygonError::SelfIntersection {
+                first: (1, 2),
+                second: (2, 3)
+            })
+        );
+        // touching at a single vertex is not allowed either
+        assert!(matches!(
+            validate(&parse("0,0\n4,0\n4,4\n8,4\n8,8\n4,8\n4,4\n0,4")),
+            Err(PolygonError::SelfIntersection { .. })
+        ));
+        // but a straight run through an extra vertex is
+        assert!(validate(&parse("0,0\n3,0\n6,0\n6,5\n0,5")).is_ok());
+        assert!(part2(&parse_loops("0,0\n5,0\n5,5\n6,5")).is_err());
+
+        // products of coordinates don't fit in an i64 anymore
+        let large = |input: &str| -> Vec<Point> {
+            parse(input)
+                .into_iter()
+                .map(|p| Point::new(p.x << 40, p.y << 40))
+                .collect()
+        };
+        assert_eq!(validate(&large(EXAMPLE)), Ok(Orientation::Clockwise));
+        let mut reversed = large(EXAMPLE);
+        reversed.reverse();
+        assert_eq!(validate(&reversed), Ok(Orientation::CounterClockwise));
+        assert_eq!(
+            validate(&large("0,0\n6,0\n3,0\n3,5\n0,5")),
+            Err(PolygonError::SelfIntersection {
+                first: (1, 2),
+                second: (2, 3)
+            })
+        );
+        assert!(validate(&large("0,0\n3,0\n6,0\n6,5\n0,5")).is_ok());
+    }
+
+    #[test]
+    fn inside_rects() {
+        for polygon in [
+            EXAMPLE,
+            U_SHAPE,
+            C_SHAPE,
+            "0,0\n2,0\n2,4\n3,4\n3,0\n5,0\n5,6\n0,6",
+        ] {
+            let edges = polygon_edges(&parse(polygon));
+            let outside = outside_tiles(&edges);
+            let max_x = edges.iter().map(|(_, b)| b.x).max().unwrap();
+            let max_y = edges.iter().map(|(_, b)| b.y).max().unwrap();
+            let mut expected = 0;
+            for left in 0..=max_x {
+                for right in left..=max_x {
+                    for top in 0..=max_y {
+                        for bottom in top..=max_y {
+                            let inside = (left..=right)
+                                .all(|x| (top..=bottom).all(|y| !outside.contains(&(x, y))));
+                            if inside {
+                                expected = expected.max((right - left + 1) * (bottom - top + 1));
+                            }
+                        }
+                    }
+                }
+            }
+
+            let rect = largest_inside_rect(&parse(polygon)).unwrap();
+            assert_eq!(rect.area(), expected as u64, "{polygon}");
+            for x in rect.left..=rect.right {
+                for y in rect.top..=rect.bottom {
+                    assert!(!outside.contains(&(x, y)), "{polygon}");
+                }
+            }
+        }
+        let rect = largest_inside_rect(&parse(EXAMPLE)).unwrap();
+        assert_eq!(
+            (rect.left, rect.top, rect.right, rect.bottom),
+            (2, 3, 11, 5)
+        );
+    }
+
+    const OCTAGON: &str = r#"2,0
+6,0
+8,2
+8,6
+6,8
+2,8
+0,6
+0,2"#;
+
+    #[test]
+    fn diagonal_edges() {
+        let triangle = polygon_edges(&parse("0,0\n10,0\n0,10"));
+        // the corner touches the hypotenuse
+        assert!(rect_is_fully_contained(&triangle, 0, 0, 5, 5));
+        assert!(!rect_is_fully_contained(&triangle, 0, 0, 5, 6));
+        assert!(rect_is_fully_contained(&triangle, 0, 5, 5, 5));
+        assert!(!rect_is_fully_contained(&triangle, 0, 5, 6, 5));
+        assert!(rect_is_fully_contained(&triangle, 1, 1, 1, 1));
+        assert!(!rect_is_fully_contained(&triangle, 6, 5, 6, 5));
+        // along the hypotenuse's ends
+        assert!(rect_is_fully_contained(&triangle, 10, 0, 10, 0));
+        assert!(!rect_is_fully_contained(&triangle, 0, 0, 10, 10));
+        // only lines fit, and part2 skips them
+        assert_eq!(
+            largest_contained_rects(&parse("0,0\n10,0\n0,10"), 1).unwrap()[0].area(),
+            11
+        );
+        assert_eq!(part2(&parse_loops("0,0\n10,0\n0,10")), Ok(0));
+
+        let input = parse(OCTAGON);
+        assert_eq!(part1(&parse_loops(OCTAGON)), 49);
+        assert_eq!(part2(&parse_loops(OCTAGON)), Ok(45));
+        let best = largest_contained_rects(&input, 1).unwrap()[0];
+        assert_eq!(best.corners, [Point::new(2, 0), Point::new(6, 8)]);
+        assert_eq!(
+            largest_inside_rect(&input),
+            Err(PolygonError::DiagonalEdge { lines: (2, 3) })
+        );
+    }
+
+    #[test]
+    fn notch_without_tiles() {
+        // tiles at x = 2 and x = 3 are next to each other, so a rectangle over the notch
+        // between them only has green tiles
+        let input = parse("0,0\n2,0\n2,4\n3,4\n3,0\n5,0\n5,6\n0,6");
+        let edges = polygon_edges(&input);
+        let grid = CompressedGrid::new(&edges);
+        for (left, top, right, bottom) in [(0, 0, 5, 6), (2, 0, 3, 4), (0, 4, 5, 6), (0, 0, 2, 6)] {
+            assert!(grid.contains_rect(left, top, right, bottom));
+            assert!(rect_is_fully_contained(&edges, left, top, right, bottom));
+        }
+        assert_eq!(
+            contained_rects(&input, &edges, 1, |_| true),
+            [Rect::new(Point::new(0, 0), Point::new(5, 6))]
+        );
+
+        // a diagonal edge elsewhere doesn't change the answer
+        let input = parse("0,0\n2,0\n2,4\n3,4\n3,0\n5,0\n5,5\n4,6\n0,6");
+        let edges = polygon_edges(&input);
+        assert!(rect_is_fully_contained(&edges, 0, 0, 3, 6));
+        assert_eq!(
+            contained_rects(&input, &edges, 1, |_| true),
+            [Rect::new(Point::new(0, 0), Point::new(5, 5))]
+        );
+    }
+
+    const WITH_HOLE: &str = r#"0,0
+10,0
+10,10
+0,10
+
+3,3
+7,3
+7,7
+3,7"#;
+
+    #[test]
+    fn holes() {
+        let loops = parse_loops(WITH_HOLE);
+        assert_eq!(loops.len(), 2);
+        assert_eq!(validate_loops(&loops), Ok(()));
+        // the full square would go over the hole
+        assert_eq!(largest_region_rects(&loops, 1).unwrap()[0].area(), 32);
+        assert_eq!(part2(&loops), Ok(32));
+        assert_eq!(part2(&loops[..1].to_vec()), Ok(121));
+
+        // the hole's own tiles are fine, but not what they surround
+        let edges = region_edges(&loops);
+        let grid = CompressedGrid::new(&edges);
+        let hole_interior = |x: i64, y: i64| (4..=6).contains(&x) && (4..=6).contains(&y);
+        let points = red_tiles(&loops);
+        for p1 in &points {
+            for p2 in &points {
+                let r = Rect::new(*p1, *p2);
+                let expected =
+                    (r.left..=r.right).all(|x| (r.top..=r.bottom).all(|y| !hole_interior(x, y)));
+                assert_eq!(
+                    grid.contains_rect(r.left, r.top, r.right, r.bottom),
+                    expected
+                );
+                assert_eq!(
+                    rect_is_fully_contained(&edges, r.left, r.top, r.right, r.bottom),
+                    expected
+                );
+            }
+        }
+        assert_eq!(grid.largest_inside_rect().area(), 44);
+
+        // an island in the hole is part of the region again
+        let island = parse_loops(&format!("{WITH_HOLE}\n\n5,5\n6,5\n6,6\n5,6"));
+        assert_eq!(validate_loops(&island), Ok(()));
+        assert!(CompressedGrid::new(&region_edges(&island)).contains_rect(5, 5, 6, 6));
+
+        // diagonal holes go through the general path
+        let diamond = parse_loops("0,0\n10,0\n10,10\n0,10\n\n5,2\n8,5\n5,8\n2,5");
+        let edges = region_edges(&diamond);
+        assert!(rect_is_fully_contained(&edges, 0, 0, 10, 2));
+        assert!(!rect_is_fully_contained(&edges, 0, 0, 10, 3));
+        assert!(rect_is_fully_contained(&edges, 0, 0, 3, 3));
+        assert!(!rect_is_fully_contained(&edges, 0, 0, 4, 4));
+    }
+
+    #[test]
+    fn loop_parsing() {
+        let loops = parse_loops("\n0,0\n10,0\n10,10\n0,10\n\n\n\n3,3\n7,3\n7,7\n3,7\n\n");
+        assert_eq!(loops.len(), 2);
+        assert_eq!(loops[0].first_line, 2);
+        assert_eq!(loops[1].first_line, 9);
+        assert_eq!(loops[1].points, parse("3,3\n7,3\n7,7\n3,7"));
+        assert_eq!(
+            parse_loops(&WITH_HOLE.replace('\n', "\r\n")),
+            parse_loops(WITH_HOLE)
+        );
+        assert_eq!(part1(&parse_loops(WITH_HOLE)), 121);
+        assert_eq!(part2(&parse_loops(WITH_HOLE)), Ok(32));
+    }
+
+    #[test]
+    fn loop_errors() {
+        // lines count across loops, blank ones included
+        assert_eq!(
+            validate_loops(&parse_loops("0,0\n10,0\n10,10\n0,10\n\n3,3\n7,3\n7,3\n3,7")),
+            Err(PolygonError::DuplicatePoint { lines: (7, 8) })
+        );
+        assert_eq!(
+            validate_loops(&parse_loops(
+                "0,0\n10,0\n10,10\n0,10\n\n\n\n3,3\n7,3\n7,3\n3,7"
+            )),
+            Err(PolygonError::DuplicatePoint { lines: (9, 10) })
+        );
+        assert_eq!(
+            validate_loops(&parse_loops(
+                "0,0\n10,0\n10,10\n0,10\n\n3,3\n10,3\n10,7\n3,7"
+            )),
+            Err(PolygonError::SelfIntersection {
+                first: (2, 3),
+                second: (6, 7)
+            })
+        );
+    }
+
+    #[test]
+    fn concave_notches() {
+        // the notch itself (153 tiles) doesn't cross any edge
+        assert_eq!(part2(&parse_loops(U_SHAPE)), Ok(57));
+        assert_eq!(part2_naive(&parse(U_SHAPE)), 57);
+        assert_eq!(part2(&parse_loops(C_SHAPE)), Ok(57));
+        assert_eq!(part2_naive(&parse(C_SHAPE)), 57);
+        assert_eq!(part2_naive(&parse(EXAMPLE)), 24);
+    }
+
+    // every tile outside of the polygon, by flood filling its bounding box
+    fn outside_tiles(edges: &[(Point, Point)]) -> HashSet<(i64, i64)> {
+        let max_x = edges.iter().map(|(_, b)| b.x).max().unwrap() + 1;
+        let max_y = edges.iter().map(|(_, b)| b.y).max().unwrap() + 1;
+        let on_edge = |x: i64, y: i64| {
+            edges
+                .iter()
+                .any(|(a, b)| a.x <= x && x <= b.x && a.y <= y && y <= b.y)
+        };
+        let mut outside = HashSet::new();
+        let mut stack = vec![(-1, -1)];
+        outside.insert((-1, -1));
+        while let Some((x, y)) = stack.pop() {
+            for (nx, ny) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+                if (-1..=max_x).contains(&nx)
+                    && (-1..=max_y).contains(&ny)
+                    && !on_edge(nx, ny)
+                    && outside.insert((nx, ny))
+                {
+                    stack.push((nx, ny));
+                }
+            }
+        }
+        outside
+    }
+
+    #[test]
+    fn containment_matches_tiles() {
+        for polygon in [EXAMPLE, U_SHAPE, C_SHAPE] {
+            let input = parse(polygon);
+            let edges = polygon_edges(&input);
+            let grid = CompressedGrid::new(&edges);
+            let outside = outside_tiles(&edges);
+            for p1 in &input {
+                for p2 in &input {
+                    let (left, right) = (p1.x.min(p2.x), p1.x.max(p2.x));
+                    let (top, bottom) = (p1.y.min(p2.y), p1.y.max(p2.y));
+                    let expected =
+                        (left..=right).all(|x| (top..=bottom).all(|y| !outside.contains(&(x, y))));
+                    assert_eq!(
+                        grid.contains_rect(left, top, right, bottom),
+                        expected,
+                        "{p1:?} {p2:?}"
+                    );
+                    assert_eq!(
+                        rect_is_fully_contained(&edges, left, top, right, bottom),
+                        expected,
+                        "{p1:?} {p2:?}"
+                    );
+                }
+            }
+        }
     }
 }
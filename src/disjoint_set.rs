@@ -0,0 +1,192 @@
+/// Union-find over `0..len`, with union by size.
+///
+/// A set created with [`DisjointSet::with_rollback`] never compresses paths,
+/// so that unions can be undone in reverse order with [`DisjointSet::rollback`].
+#[derive(Debug, Clone)]
+pub struct DisjointSet {
+    parent: Vec<usize>,
+    // only meaningful for roots
+    size: Vec<usize>,
+    // circular linked list through the members of each component
+    next: Vec<usize>,
+    components: usize,
+    // (attached root, root it was attached to) for every union, in rollback mode only
+    history: Option<Vec<(usize, usize)>>,
+}
+
+impl DisjointSet {
+    pub fn new(len: usize) -> Self {
+        Self {
+            parent: (0..len).collect(),
+            size: vec![1; len],
+            next: (0..len).collect(),
+            components: len,
+            history: None,
+        }
+    }
+
+    pub fn with_rollback(len: usize) -> Self {
+        Self {
+            history: Some(Vec::new()),
+            ..Self::new(len)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.parent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parent.is_empty()
+    }
+
+    pub fn components(&self) -> usize {
+        self.components
+    }
+
+    pub fn find(&mut self, mut x: usize) -> usize {
+        if self.history.is_some() {
+            return self.root(x);
+        }
+        while self.parent[x] != x {
+            self.parent[x] = self.parent[self.parent[x]];
+            x = self.parent[x];
+        }
+        x
+    }
+
+    /// Same as `find`, without compressing the path.
+    pub fn root(&self, mut x: usize) -> usize {
+        while self.parent[x] != x {
+            x = self.parent[x];
+        }
+        x
+    }
+
+    /// Merges the components of `x` and `y`, returns false if they already were the same.
+    pub fn union(&mut self, x: usize, y: usize) -> bool {
+        let mut root_x = self.find(x);
+        let mut root_y = self.find(y);
+        if root_x == root_y {
+            return false;
+        }
+        if self.size[root_x] < self.size[root_y] {
+            std::mem::swap(&mut root_x, &mut root_y);
+        }
+        self.parent[root_y] = root_x;
+        self.size[root_x] += self.size[root_y];
+        // splices the two member lists together
+        self.next.swap(root_x, root_y);
+        self.components -= 1;
+        if let Some(history) = &mut self.history {
+            history.push((root_y, root_x));
+        }
+        true
+    }
+
+    pub fn same(&mut self, x: usize, y: usize) -> bool {
+        self.find(x) == self.find(y)
+    }
+
+    /// Size of the component containing `x`.
+    pub fn size(&mut self, x: usize) -> usize {
+        let root = self.find(x);
+        self.size[root]
+    }
+
+    /// Every member of the component containing `x`, starting with `x`.
+    pub fn members(&self, x: usize) -> impl Iterator<Item = usize> + '_ {
+        let mut cur = Some(x);
+        std::iter::from_fn(move || {
+            let res = cur?;
+            let next = self.next[res];
+            cur = (next != x).then_some(next);
+            Some(res)
+        })
+    }
+
+    /// The root of every component.
+    pub fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len()).filter(|&i| self.parent[i] == i)
+    }
+
+    pub fn component_sizes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.roots().map(|root| self.size[root])
+    }
+
+    /// Number of unions that can be undone, to pass to [`DisjointSet::rollback`] later.
+    pub fn snapshot(&self) -> usize {
+        self.history
+            .as_ref()
+            .expect("snapshot needs a set created with `with_rollback`")
+            .len()
+    }
+
+    /// Undoes every union done since `snapshot`.
+    pub fn rollback(&mut self, snapshot: usize) {
+        let history = self
+            .history
+            .as_mut()
+            .expect("rollback needs a set created with `with_rollback`");
+        while history.len() > snapshot {
+            let (child, root) = history.pop().unwrap();
+            self.next.swap(root, child);
+            self.size[root] -= self.size[child];
+            self.parent[child] = child;
+            self.components += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sorted_members(set: &DisjointSet, x: usize) -> Vec<usize> {
+        let mut members: Vec<usize> = set.members(x).collect();
+        members.sort_unstable();
+        members
+    }
+
+    #[test]
+    fn union_sizes_and_members() {
+        let mut set = DisjointSet::new(6);
+        assert!(set.union(0, 1));
+        assert!(set.union(2, 3));
+        assert!(set.union(1, 3));
+        assert!(!set.union(0, 2));
+        assert_eq!(set.components(), 3);
+        assert_eq!(set.size(3), 4);
+        assert!(set.same(0, 2));
+        assert_eq!(sorted_members(&set, 2), vec![0, 1, 2, 3]);
+        assert_eq!(set.members(5).collect::<Vec<_>>(), vec![5]);
+
+        let mut sizes: Vec<usize> = set.component_sizes().collect();
+        sizes.sort_unstable();
+        assert_eq!(sizes, vec![1, 1, 4]);
+    }
+
+    #[test]
+    fn rollback() {
+        let mut set = DisjointSet::with_rollback(5);
+        set.union(0, 1);
+        let snapshot = set.snapshot();
+        set.union(2, 3);
+        set.union(1, 3);
+        set.union(3, 4);
+        assert_eq!(set.components(), 1);
+
+        set.rollback(snapshot);
+        assert_eq!(set.components(), 4);
+        assert_eq!(set.size(0), 2);
+        assert!(!set.same(1, 2));
+        assert_eq!(sorted_members(&set, 1), vec![0, 1]);
+        assert_eq!(sorted_members(&set, 3), vec![3]);
+
+        // still usable after a rollback
+        assert!(set.union(4, 0));
+        assert_eq!(sorted_members(&set, 4), vec![0, 1, 4]);
+        set.rollback(0);
+        assert_eq!(set.components(), 5);
+    }
+}
@@ -1,14 +1,17 @@
+pub mod automaton;
 mod day1;
 mod day10;
 mod day11;
-mod day12;
+pub mod day12;
 mod day2;
 mod day3;
-mod day4;
+pub mod day4;
 mod day5;
 mod day6;
-mod day7;
-mod day8;
-mod day9;
+pub mod day7;
+pub mod day8;
+pub mod day9;
+pub mod disjoint_set;
+mod rng;
 
 aoc_runner_derive::aoc_lib! { year = 2025 }
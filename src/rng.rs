@@ -0,0 +1,41 @@
+/// Small seedable PRNG (SplitMix64), so generated inputs are reproducible.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `0..n`, without modulo bias.
+    pub fn below(&mut self, n: u64) -> u64 {
+        assert!(n > 0);
+        // reject the top values that would make some results more likely
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let v = self.next_u64();
+            if v < zone {
+                return v % n;
+            }
+        }
+    }
+
+    /// Same as `below` for bigger ranges.
+    pub fn below_u128(&mut self, n: u128) -> u128 {
+        assert!(n > 0);
+        let zone = u128::MAX - u128::MAX % n;
+        loop {
+            let v = ((self.next_u64() as u128) << 64) | self.next_u64() as u128;
+            if v < zone {
+                return v % n;
+            }
+        }
+    }
+}